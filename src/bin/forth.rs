@@ -0,0 +1,154 @@
+use std::io::{self, BufRead, Write};
+use rust_exercism::hard::forth::{Error, ErrorKind, Forth, Position};
+
+fn main() {
+    let mut forth = Forth::new().with_floats().with_output(io::stdout());
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else { break };
+        match interpret(&mut forth, &line, &mut io::stdout()) {
+            Ok(()) => println!(" ok"),
+            Err(e) => println!(" {e}"),
+        }
+        io::stdout().flush().unwrap();
+    }
+}
+
+/// `words` and `see` need the dictionary itself, so they are handled here
+/// and the text in between is passed to the interpreter as is.
+/// They are commands only at the top level and while no user word has taken their name
+fn interpret(forth: &mut Forth, line: &str, out: &mut impl Write) -> Result<(), Error> {
+    let mut tokens = Forth::tokens(line).into_iter();
+    let mut start = 0;
+    let mut in_definition = false;
+    // words defined earlier on the line are not in the dictionary yet
+    let mut defined = Vec::new();
+
+    while let Some((range, token)) = tokens.next() {
+        match token.as_str() {
+            ":" | "variable" | "constant" if !in_definition => {
+                in_definition = token == ":";
+                defined.extend(tokens.next().map(|(_, name)| name));
+            }
+            ";" => in_definition = false,
+            "include" if !in_definition => {
+                tokens.next();
+            }
+            "words" | "see"
+                if !in_definition && !defined.contains(&token) && forth.see(&token).is_none() =>
+            {
+                eval(forth, line, start, range.start)?;
+                if token == "words" {
+                    writeln!(out, "{}", forth.words().join(" ")).map_err(|_| ErrorKind::Output)?;
+                    start = range.end;
+                } else {
+                    let (range, name) = tokens.next().ok_or(ErrorKind::InvalidWord)?;
+                    let written = match forth.see(&name) {
+                        Some(source) => writeln!(out, "{source}"),
+                        None if forth.is_builtin(&name) => writeln!(out, "{name} is a primitive"),
+                        None => {
+                            return Err(Error {
+                                word: Some(name),
+                                position: Some(Position { line: 1, offset: range.start }),
+                                ..ErrorKind::UnknownWord.into()
+                            });
+                        }
+                    };
+                    written.map_err(|_| ErrorKind::Output)?;
+                    start = range.end;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    eval(forth, line, start, line.len())
+}
+
+/// Evaluates `line[start..end]`, reporting error offsets relative to the whole line
+fn eval(forth: &mut Forth, line: &str, start: usize, end: usize) -> Result<(), Error> {
+    forth.eval(&line[start..end]).map_err(|mut e| {
+        if let Some(position) = &mut e.position {
+            position.offset += start;
+        }
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(forth: &mut Forth, line: &str) -> (Result<(), Error>, String) {
+        let mut out = Vec::new();
+        let result = interpret(forth, line, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn words_and_see_are_commands_at_the_top_level() {
+        let mut forth = Forth::new();
+        let (result, out) = run(&mut forth, ": sq dup * ; 3 sq words see sq 1 +");
+        assert!(result.is_ok());
+        assert_eq!(out, format!("{}\n: sq dup * ;\n", forth.words().join(" ")));
+        assert!(out.starts_with("sq + - * / dup "));
+        assert_eq!(forth.stack(), [10]);
+    }
+
+    #[test]
+    fn commands_inside_strings_and_definitions_are_left_to_the_interpreter() {
+        let mut forth = Forth::new();
+        let (result, out) = run(&mut forth, r#": hi ." see you" cr ; hi"#);
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        assert_eq!(forth.take_output(), "see you\n");
+
+        let (result, _) = run(&mut forth, ": show words ;");
+        assert_eq!(result.unwrap_err(), ErrorKind::UnknownWord);
+    }
+
+    #[test]
+    fn user_words_named_like_commands_take_precedence() {
+        let mut forth = Forth::new();
+        let (result, out) = run(&mut forth, ": words 42 ; words");
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        assert_eq!(forth.stack(), [42]);
+
+        let (result, out) = run(&mut forth, "variable see see");
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+        assert_eq!(forth.stack().len(), 2);
+    }
+
+    #[test]
+    fn see_tells_primitives_apart() {
+        let mut forth = Forth::new();
+        let (result, out) = run(&mut forth, "see DUP see if");
+        assert!(result.is_ok());
+        assert_eq!(out, "dup is a primitive\nif is a primitive\n");
+    }
+
+    #[test]
+    fn error_offsets_are_relative_to_the_line() {
+        let mut forth = Forth::new();
+        let error = run(&mut forth, "words 1 foo").0.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownWord);
+        assert_eq!(error.position.unwrap().offset, 8);
+
+        let error = run(&mut forth, "see nothing").0.unwrap_err();
+        assert_eq!(error.to_string(), "undefined word in 'nothing' at line 1, offset 4");
+        assert_eq!(run(&mut forth, "1 see").0.unwrap_err(), ErrorKind::InvalidWord);
+    }
+
+    #[test]
+    fn words_whose_lowercase_has_another_length() {
+        let mut forth = Forth::new();
+        assert!(run(&mut forth, ": ẞ 1 ;").0.is_ok());
+        let (result, out) = run(&mut forth, "see ẞ 2 words 3");
+        assert!(result.is_ok());
+        assert!(out.starts_with(": ß 1 ;\nß + "));
+        assert_eq!(forth.stack(), [2, 3]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+use std::thread;
+use anyhow::{anyhow, Error};
+use rust_exercism::medium::grep::{self, Flags};
+
+/// Short options that take a value, either attached (`-A2`) or as the next argument
+const VALUE_OPTIONS: &str = "ABCmef";
+
+const USAGE: &str = "usage: grep [OPTION]... PATTERNS [FILE]...";
+
+/// Operand naming standard input. It can only be the sole file
+const STDIN: &str = "-";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    flags: Vec<String>,
+    patterns: Vec<String>,
+    files: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1);
+    let code = run(args, io::stdin().lock(), &mut io::stdout().lock(), &mut io::stderr());
+    ExitCode::from(code)
+}
+
+/// Exit codes follow grep: 0 if a line was selected, 1 if none was, 2 on any error
+fn run(
+    args: impl Iterator<Item = String>,
+    stdin: impl BufRead,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> u8 {
+    match search(args, stdin, out, err) {
+        Ok(code) => code,
+        Err(e) => {
+            // nothing more can be reported if stderr is gone
+            let _ = writeln!(err, "grep: {e}");
+            2
+        }
+    }
+}
+
+fn search(
+    args: impl Iterator<Item = String>,
+    stdin: impl BufRead,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<u8, Error> {
+    let mut args = parse(args)?;
+    let patterns: Vec<&str> = args.patterns.iter().map(String::as_str).collect();
+    let flags: Vec<&str> = args.flags.iter().map(String::as_str).collect();
+    let flags = Flags::new(&flags);
+
+    if args.files.is_empty() && args.flags.iter().any(|f| f == "-r") {
+        args.files.push(".".to_string());
+    }
+    if args.files.len() > 1 && args.files.iter().any(|f| f == STDIN) {
+        return Err(anyhow!("'{STDIN}' cannot be combined with other files"));
+    }
+    let search = if args.files.is_empty() || args.files == [STDIN] {
+        grep::search_reader_patterns(&patterns, &flags, stdin)?
+    } else {
+        let files: Vec<&str> = args.files.iter().map(String::as_str).collect();
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        grep::search_patterns(&patterns, &flags, &files, workers)?
+    };
+
+    let printed = search.lines.iter()
+        .try_for_each(|line| writeln!(out, "{line}"))
+        .and_then(|()| out.flush());
+    // A reader such as `head` may stop early, which is not an error
+    if let Err(e) = printed && e.kind() != io::ErrorKind::BrokenPipe {
+        return Err(e.into());
+    }
+    for error in &search.errors {
+        writeln!(err, "grep: {error}")?;
+    }
+
+    Ok(match (search.errors.is_empty(), search.selected) {
+        (false, _) => 2,
+        (true, 0) => 1,
+        (true, _) => 0,
+    })
+}
+
+/// Splits clustered short options (`-inv`) and permutes operands after options, as GNU grep does.
+/// The first operand is the pattern unless `-e` or `-f` gave one
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, Error> {
+    let mut parsed = Args::default();
+    let mut patterns: Option<Vec<String>> = None;
+    let mut operands = vec![];
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            operands.extend(args.by_ref());
+            break;
+        }
+        if arg.starts_with("--") {
+            parsed.flags.push(arg);
+            continue;
+        }
+        let Some(cluster) = arg.strip_prefix('-').filter(|c| !c.is_empty()) else {
+            operands.push(arg);
+            continue;
+        };
+
+        for (i, c) in cluster.char_indices() {
+            if !VALUE_OPTIONS.contains(c) {
+                parsed.flags.push(format!("-{c}"));
+                continue;
+            }
+            let rest = &cluster[i + c.len_utf8()..];
+            let value = match rest {
+                "" => args.next().ok_or_else(|| anyhow!("option '-{c}' requires an argument"))?,
+                rest => rest.to_string(),
+            };
+            match c {
+                'e' => patterns.get_or_insert_default().push(value),
+                'f' => {
+                    let file = fs::read_to_string(&value).map_err(|e| anyhow!("{value}: {e}"))?;
+                    patterns.get_or_insert_default().extend(file.lines().map(String::from));
+                }
+                _ => {
+                    parsed.flags.push(format!("-{c}"));
+                    parsed.flags.push(value);
+                }
+            }
+            break;
+        }
+    }
+
+    let mut operands = operands.into_iter();
+    parsed.patterns = match patterns {
+        Some(patterns) => patterns,
+        None => vec![operands.next().ok_or_else(|| anyhow!(USAGE))?],
+    };
+    parsed.files = operands.collect();
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    /// Runs the command on `stdin`, returning the exit code, stdout and stderr
+    fn grep(arguments: &[&str], stdin: &str) -> (u8, String, String) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let code = run(args(arguments), stdin.as_bytes(), &mut out, &mut err);
+        (code, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    /// Sample file in the temp directory, removed on drop
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("grep-bin-{}-{name}", std::process::id()));
+            fs::write(&path, content).unwrap();
+            Self(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_splits_clustered_flags() {
+        let parsed = parse(args(&["-inv", "pat", "a.txt", "b.txt"])).unwrap();
+        assert_eq!(parsed, Args {
+            flags: strings(&["-i", "-n", "-v"]),
+            patterns: strings(&["pat"]),
+            files: strings(&["a.txt", "b.txt"]),
+        });
+    }
+
+    #[test]
+    fn parse_reads_values_attached_or_separate() {
+        let parsed = parse(args(&["-nA2", "-B", "1", "-m3", "pat"])).unwrap();
+        assert_eq!(parsed.flags, strings(&["-n", "-A", "2", "-B", "1", "-m", "3"]));
+        assert_eq!(parsed.patterns, strings(&["pat"]));
+
+        let parsed = parse(args(&["-ie", "first", "-efoo", "-vC1", "file"])).unwrap();
+        assert_eq!(parsed.flags, strings(&["-i", "-v", "-C", "1"]));
+        assert_eq!(parsed.patterns, strings(&["first", "foo"]));
+        assert_eq!(parsed.files, strings(&["file"]));
+    }
+
+    #[test]
+    fn parse_reads_pattern_files() {
+        let patterns = TempFile::new("patterns.txt", "one\ntwo\n");
+        let parsed = parse(args(&["-f", &patterns.0, "-e", "three", "file"])).unwrap();
+        assert_eq!(parsed.patterns, strings(&["one", "two", "three"]));
+        assert_eq!(parsed.files, strings(&["file"]));
+
+        let error = parse(args(&["-f", "grep-bin-missing-patterns"])).unwrap_err();
+        assert!(error.to_string().starts_with("grep-bin-missing-patterns: "));
+    }
+
+    #[test]
+    fn parse_permutes_operands_until_double_dash() {
+        let arguments = ["pat", "a.txt", "-n", "--include=*.rs", "--", "-v", "-"];
+        let parsed = parse(args(&arguments)).unwrap();
+        assert_eq!(parsed, Args {
+            flags: strings(&["-n", "--include=*.rs"]),
+            patterns: strings(&["pat"]),
+            files: strings(&["a.txt", "-v", "-"]),
+        });
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(args(&["-n"])).unwrap_err().to_string(), USAGE);
+        let error = parse(args(&["pat", "-A"])).unwrap_err();
+        assert_eq!(error.to_string(), "option '-A' requires an argument");
+    }
+
+    #[test]
+    fn exit_codes() {
+        let input = "alpha\nbeta\ngamma\n";
+        assert_eq!(grep(&["-n", "e"], input), (0, "2:beta\n".into(), String::new()));
+        assert_eq!(grep(&["delta"], input), (1, String::new(), String::new()));
+        assert_eq!(grep(&["-c", "delta"], input), (1, "0\n".into(), String::new()));
+        assert_eq!(grep(&["-vc", "delta"], input), (0, "3\n".into(), String::new()));
+
+        let (code, out, err) = grep(&["-z", "alpha"], input);
+        assert_eq!((code, out.as_str(), err.as_str()), (2, "", "grep: unknown flag '-z'\n"));
+        assert_eq!(grep(&[], input).0, 2);
+        assert_eq!(grep(&["-E", "a("], input).0, 2);
+    }
+
+    #[test]
+    fn unreadable_files_exit_with_two_after_searching_the_rest() {
+        let file = TempFile::new("sample.txt", "alpha\nbeta\n");
+        let (code, out, err) = grep(&["-e", "beta", "-e", "alp", "grep-bin-missing", &file.0], "");
+        assert_eq!(code, 2);
+        assert_eq!(out, format!("{0}:alpha\n{0}:beta\n", file.0));
+        assert!(err.starts_with("grep: grep-bin-missing: "));
+    }
+
+    #[test]
+    fn dash_reads_standard_input() {
+        let input = "alpha\nbeta\n";
+        let expected = (0, "(standard input)\n".into(), String::new());
+        assert_eq!(grep(&["-l", "beta", "-"], input), expected);
+
+        let file = TempFile::new("with-stdin.txt", "beta\n");
+        let (code, _, err) = grep(&["beta", "-", &file.0], input);
+        assert_eq!((code, err.as_str()), (2, "grep: '-' cannot be combined with other files\n"));
+    }
+}
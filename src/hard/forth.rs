@@ -1,20 +1,416 @@
 use std::collections::HashMap;
-
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Default cell type
 pub type Value = i32;
 pub type Result = std::result::Result<(), Error>;
+type Step<T = ()> = std::result::Result<T, ErrorKind>;
+
+/// Upper bound for `ALLOT`/`VARIABLE`, in cells
+const MEMORY_LIMIT: usize = 1 << 20;
+
+/// Default limit of nested calls, see `Forth::with_max_call_depth`
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// Limit of nested `INCLUDE`s, mostly to catch files including themselves
+const MAX_INCLUDE_DEPTH: usize = 64;
+
+const CONTROL_WORDS: [&str; 11] = [
+    "if", "else", "then", "do", "loop", "begin", "until", "while", "repeat", "recurse", "exit",
+];
+
+/// Words compiled to a single instruction, see `compile_word`
+const PRIMITIVES: [&str; 27] = [
+    "+", "-", "*", "/", "dup", "drop", "swap", "over", "=", "<", ">", "i", "j", "@", "!", "+!",
+    "allot", "here", "cells", ".", ".s", "emit", "cr", "type", ">r", "r>", "r@",
+];
+
+/// Words of the floating point stack, see `Forth::with_floats`
+const FLOAT_PRIMITIVES: [&str; 7] = ["f+", "f-", "f*", "f/", "f.", "s>f", "f>s"];
+
+/// Words handled by `eval` and `compile` themselves
+const DEFINING_WORDS: [&str; 6] = [":", ";", "variable", "constant", "include", ".\""];
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Integer type of the data stack and memory cells. Sealed, since the interpreter relies
+/// on cells being signed: it is implemented for `i32`, `i64` and `i128` only
+pub trait Cell:
+    sealed::Sealed + Copy + Default + PartialOrd + FromStr + fmt::Display + fmt::Debug + 'static
+{
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    fn checked_mul(self, rhs: Self) -> Option<Self>;
+    fn checked_div(self, rhs: Self) -> Option<Self>;
+    fn from_i128(n: i128) -> Option<Self>;
+    fn as_i128(self) -> i128;
+}
+
+macro_rules! impl_cell {
+    ($($t:ty),*) => {
+        $(impl sealed::Sealed for $t {}
+
+        impl Cell for $t {
+            fn checked_add(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_add(self, rhs)
+            }
+
+            fn checked_sub(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_sub(self, rhs)
+            }
+
+            fn checked_mul(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_mul(self, rhs)
+            }
+
+            fn checked_div(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_div(self, rhs)
+            }
+
+            fn from_i128(n: i128) -> Option<Self> {
+                Self::try_from(n).ok()
+            }
+
+            fn as_i128(self) -> i128 {
+                self as i128
+            }
+        })*
+    };
+}
+
+impl_cell!(i32, i64, i128);
 
 #[derive(Debug, Default)]
-pub struct Forth {
-    stack: Vec<Value>,
-    words: HashMap<String, Vec<String>>,
+pub struct Forth<C = Value> {
+    stack: Vec<C>,
+    /// separate floating point stack, `None` unless enabled with `with_floats`
+    floats: Option<Vec<f64>>,
+    words: HashMap<String, usize>,
+    definitions: Vec<Definition<C>>,
+    memory: Vec<C>,
+    natives: Vec<Native<C>>,
+    output: Output,
+    /// `MAX_CALL_DEPTH` unless set with `with_max_call_depth`
+    max_call_depth: Option<usize>,
+    include_depth: usize,
+}
+
+/// Where the output words print to
+enum Output {
+    Buffer(Vec<u8>),
+    Writer(Box<dyn Write>),
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::Buffer(Vec::new())
+    }
+}
+
+impl Output {
+    fn write(&mut self, text: &str) -> Step {
+        match self {
+            Output::Buffer(buffer) => buffer.extend_from_slice(text.as_bytes()),
+            Output::Writer(writer) => {
+                writer.write_all(text.as_bytes()).map_err(|_| ErrorKind::Output)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Buffer(buffer) => f.debug_tuple("Buffer").field(&buffer.len()).finish(),
+            Output::Writer(_) => f.write_str("Writer"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Definition<C> {
+    name: String,
+    source: String,
+    code: Vec<Op<C>>,
+    /// source word of every op, for error reporting
+    words: Vec<String>,
+    /// text of the `."` strings, referenced by index
+    strings: Vec<String>,
+    /// `None` if it depends on the data, e.g. branches leaving different numbers of values
+    effect: Option<StackEffect>,
+}
+
+impl<C> Definition<C> {
+    fn new(code: Vec<Op<C>>, words: Vec<String>) -> Self {
+        Self {
+            name: String::new(),
+            source: String::new(),
+            code,
+            words,
+            strings: Vec::new(),
+            effect: None,
+        }
+    }
+}
+
+struct Token {
+    text: String,
+    position: Position,
+    /// byte offset just past the token in the input, which may differ from
+    /// `offset + text.len()` since the text is lowercased
+    end: usize,
+}
+
+/// Number of values a word takes from and leaves on the stack, `( inputs -- outputs )`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEffect {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl StackEffect {
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        Self { inputs, outputs }
+    }
+}
+
+impl fmt::Display for StackEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "( {} -- {} )", self.inputs, self.outputs)
+    }
+}
+
+/// Stack depths relative to the start of a definition, `None` where they depend on the data
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Depths {
+    data: Option<isize>,
+    returns: Option<isize>,
+}
+
+impl Depths {
+    fn merge(self, other: Depths) -> Depths {
+        Depths {
+            data: self.data.filter(|&d| other.data == Some(d)),
+            returns: self.returns.filter(|&d| other.returns == Some(d)),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum Error {
+type NativeFn<C> = Box<dyn FnMut(&mut Vec<C>) -> Step>;
+
+struct Native<C> {
+    effect: StackEffect,
+    func: NativeFn<C>,
+}
+
+impl<C> fmt::Debug for Native<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Native").field("effect", &self.effect).finish()
+    }
+}
+
+/// Active call of a definition
+struct Frame {
+    definition: usize,
+    /// next op to execute
+    pc: usize,
+    /// depth of the loop and return stacks at the call, restored on return
+    loops: usize,
+    returns: usize,
+}
+
+/// Execution state of a single `eval` run
+struct Control<C> {
+    frames: Vec<Frame>,
+    /// (index, limit) of every active DO loop, innermost last
+    loops: Vec<(C, C)>,
+    /// values moved with `>R`
+    returns: Vec<C>,
+}
+
+impl<C> Control<C> {
+    fn call(&mut self, definition: usize) {
+        let (loops, returns) = (self.loops.len(), self.returns.len());
+        self.frames.push(Frame { definition, pc: 0, loops, returns });
+    }
+
+    /// Leaves the innermost definition, dropping whatever it left on the loop
+    /// and return stacks
+    fn exit(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            self.loops.truncate(frame.loops);
+            self.returns.truncate(frame.returns);
+        }
+    }
+}
+
+/// Location of a token in the evaluated input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// 1-based line number
+    pub line: usize,
+    /// byte offset from the start of the input
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// the word that failed
+    pub word: Option<String>,
+    /// the top-level token being evaluated when the failure happened
+    pub position: Option<Position>,
+    /// user-defined words being executed at the failure, outermost first
+    pub call_chain: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
     DivisionByZero,
     StackUnderflow,
     UnknownWord,
     InvalidWord,
+    UnbalancedControl,
+    MisplacedControl,
+    InvalidAddress,
+    OutOfMemory,
+    StackEffectMismatch,
+    Overflow,
+    UnterminatedString,
+    InvalidCharacter,
+    Output,
+    RecursionLimit,
+    File,
+}
+
+impl Error {
+    fn at(kind: ErrorKind, token: &Token) -> Self {
+        Self {
+            kind,
+            word: Some(token.text.clone()),
+            position: Some(token.position),
+            call_chain: Vec::new(),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, word: None, position: None, call_chain: Vec::new() }
+    }
+}
+
+impl PartialEq<ErrorKind> for Error {
+    fn eq(&self, other: &ErrorKind) -> bool {
+        self.kind == *other
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ErrorKind::DivisionByZero => "division by zero",
+            ErrorKind::StackUnderflow => "stack underflow",
+            ErrorKind::UnknownWord => "undefined word",
+            ErrorKind::InvalidWord => "invalid word name",
+            ErrorKind::UnbalancedControl => "unbalanced control structure",
+            ErrorKind::MisplacedControl => "misplaced control word",
+            ErrorKind::InvalidAddress => "invalid memory address",
+            ErrorKind::OutOfMemory => "out of memory",
+            ErrorKind::StackEffectMismatch => "stack effect mismatch",
+            ErrorKind::Overflow => "arithmetic overflow",
+            ErrorKind::UnterminatedString => "unterminated string",
+            ErrorKind::InvalidCharacter => "invalid character",
+            ErrorKind::Output => "output error",
+            ErrorKind::RecursionLimit => "recursion depth limit exceeded",
+            ErrorKind::File => "cannot read file",
+        };
+        f.write_str(message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(word) = &self.word {
+            write!(f, " in '{word}'")?;
+        }
+        if let Some(Position { line, offset }) = self.position {
+            write!(f, " at line {line}, offset {offset}")?;
+        }
+        if !self.call_chain.is_empty() {
+            // a recursive word is in the chain once per level, so repeats are counted instead
+            let calls: Vec<String> = self
+                .call_chain
+                .chunk_by(|a, b| a == b)
+                .map(|calls| match calls.len() {
+                    1 => calls[0].clone(),
+                    count => format!("{} (x{count})", calls[0]),
+                })
+                .collect();
+            write!(f, " (inside {})", calls.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compiled instruction; jump targets are positions inside the same definition
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op<C> {
+    Push(C),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Dup,
+    Drop,
+    Swap,
+    Over,
+    Eq,
+    Lt,
+    Gt,
+    Call(usize),
+    Exit,
+    Jump(usize),
+    JumpIfZero(usize),
+    Do,
+    Loop(usize),
+    I,
+    J,
+    Fetch,
+    Store,
+    AddStore,
+    Allot,
+    Here,
+    Cells,
+    Native(usize),
+    FPush(f64),
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FPrint,
+    ToFloat,
+    ToCell,
+    Print,
+    PrintStack,
+    Emit,
+    Cr,
+    Type,
+    PrintString(usize),
+    ToReturn,
+    FromReturn,
+    FetchReturn,
 }
 
 impl Forth {
@@ -22,209 +418,769 @@ impl Forth {
         Forth::default()
     }
 
-    pub fn stack(&self) -> &[Value] {
+    /// Words of `input` split the way `eval` splits them, lowercased, with the byte range
+    /// they take in `input`. A `." text"` string is a single word
+    pub fn tokens(input: &str) -> Vec<(Range<usize>, String)> {
+        Self::tokenize(input).into_iter().map(|t| (t.position.offset..t.end, t.text)).collect()
+    }
+}
+
+impl<C: Cell> Forth<C> {
+    /// Enables the floating point stack and the `F+ F- F* F/ F. S>F F>S` words
+    pub fn with_floats(mut self) -> Self {
+        self.floats = Some(Vec::new());
+        self
+    }
+
+    /// Sends everything printed by the output words to `output`
+    /// instead of the internal buffer
+    pub fn with_output<W: Write + 'static>(mut self, output: W) -> Self {
+        self.output = Output::Writer(Box::new(output));
+        self
+    }
+
+    /// Returns and clears everything printed so far, unless an output writer is used
+    pub fn take_output(&mut self) -> String {
+        match &mut self.output {
+            Output::Buffer(buffer) => String::from_utf8_lossy(&std::mem::take(buffer)).into_owned(),
+            Output::Writer(_) => String::new(),
+        }
+    }
+
+    /// Limits how many definitions may be executing at once, so runaway
+    /// recursion fails with `ErrorKind::RecursionLimit`
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    pub fn stack(&self) -> &[C] {
         &self.stack
     }
 
+    pub fn float_stack(&self) -> &[f64] {
+        self.floats.as_deref().unwrap_or_default()
+    }
+
     pub fn eval(&mut self, input: &str) -> Result {
-        let mut tokens = input
-            .split_whitespace()
-            .map(|s| s.to_lowercase())
-            .peekable();
+        let mut tokens = Self::tokenize(input).into_iter();
 
+        let mut pending = Vec::new();
         while let Some(token) = tokens.next() {
-            if token == ":" {
-                let name = tokens.next().ok_or(Error::InvalidWord)?;
-                if name.parse::<Value>().is_ok() {
-                    return Err(Error::InvalidWord);
-                }
-
-                let mut definition = Vec::new();
-                while let Some(t) = tokens.peek() {
-                    if t == ";" {
-                        tokens.next();
-                        break;
+            if !matches!(token.text.as_str(), ":" | "variable" | "constant" | "include") {
+                pending.push(token);
+                continue;
+            }
+            self.run(&pending)?;
+            pending.clear();
+
+            if token.text == "include" {
+                let path = tokens.next().ok_or_else(|| Error::at(ErrorKind::File, &token))?;
+                // the token is lowercased, but paths are case-sensitive
+                let raw = input[path.position.offset..].split_whitespace().next();
+                let raw = raw.unwrap_or_default();
+                // errors of nested includes already name the file that failed
+                self.load(raw).map_err(|e| match e.kind {
+                    ErrorKind::File | ErrorKind::RecursionLimit if e.word.is_none() => {
+                        Error { word: Some(raw.into()), ..Error::at(e.kind, &path) }
                     }
-                    let token = tokens.next().unwrap();
+                    _ => e,
+                })?;
+                continue;
+            }
 
-                    if let Some(def) = self.words.get(&token) {
-                        definition.extend(def.iter().cloned());
+            let name = tokens.next().ok_or_else(|| Error::at(ErrorKind::InvalidWord, &token))?;
+            self.check_name(&name.text).map_err(|kind| Error::at(kind, &name))?;
+            let name = name.text;
+
+            let (source, mut definition) = match token.text.as_str() {
+                ":" => {
+                    let body: Vec<Token> = tokens.by_ref().take_while(|t| t.text != ";").collect();
+                    // words used in the body are resolved now, so redefining them later
+                    // doesn't affect this definition
+                    let (definition, origins) =
+                        self.compile(&body, Some(self.definitions.len()))?;
+                    let effect = self.analyze(&definition.code).map_err(|at| {
+                        Error::at(ErrorKind::StackUnderflow, &body[origins[at]])
+                    })?;
+                    let definition = if Self::is_noop(&definition.code, effect) {
+                        Definition { effect, ..Definition::new(Vec::new(), Vec::new()) }
                     } else {
-                        definition.push(token);
-                    }
+                        Definition { effect, ..definition }
+                    };
+                    let mut source = vec![":", &name];
+                    source.extend(body.iter().map(|t| t.text.as_str()));
+                    source.push(";");
+                    (source.join(" "), definition)
                 }
-
-                // optimize and remove no-op word definitions
-                let optimized = self.optimize_definition(&definition);
-                if !optimized.is_empty() {
-                    self.words.insert(name, optimized);
-                } else {
-                    self.words.remove(&name);
+                "variable" => {
+                    let address = self.here().map_err(|kind| Error::at(kind, &token))?;
+                    self.allot(1).map_err(|kind| Error::at(kind, &token))?;
+                    let code = vec![Op::Push(address)];
+                    (format!("variable {name}"), Self::literal(code, &name))
                 }
-
-            } else if let Some(def) = self.words.get(&token) {
-                let def_copy = def.clone();
-                for token in &def_copy {
-                    self.eval_token(token)?;
+                _ => {
+                    let value = self.pop().map_err(|kind| Error::at(kind, &token))?;
+                    let code = vec![Op::Push(value)];
+                    (format!("{value} constant {name}"), Self::literal(code, &name))
                 }
-            } else {
-                self.eval_token(&token)?;
-            }
+            };
+            definition.name = name;
+            definition.source = source;
+            self.define(definition);
+        }
+
+        self.run(&pending)
+    }
+
+    /// Registers a word implemented in Rust. `func` gets the whole data stack but must
+    /// take and leave exactly as many values as declared by `effect`.
+    pub fn define_native<F>(&mut self, name: &str, effect: StackEffect, func: F) -> Result
+    where
+        F: FnMut(&mut Vec<C>) -> std::result::Result<(), ErrorKind> + 'static,
+    {
+        let name = name.to_lowercase();
+        let invalid = || Error { word: Some(name.clone()), ..ErrorKind::InvalidWord.into() };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(invalid());
         }
+        self.check_name(&name).map_err(|_| invalid())?;
 
+        self.natives.push(Native { effect, func: Box::new(func) });
+        let source = format!("native {name} {effect}");
+        let code = vec![Op::Native(self.natives.len() - 1)];
+        let definition = Definition::new(code, vec![name.clone()]);
+        self.define(Definition { name, source, effect: Some(effect), ..definition });
         Ok(())
     }
 
-    fn optimize_definition(&self, definition: &[String]) -> Vec<String> {
-        let mut stack_effect = 0;
-        let mut temp = definition.to_vec();
-
-        // Проходим по токенам и вычисляем stack effect
-        while let Some(token) = temp.pop() {
-            match token.as_str() {
-                // Базовые операции
-                "+" | "-" | "*" | "/" | "drop" => stack_effect -= 1,
-                "swap" => stack_effect += 0,
-                "over" | "dup" => stack_effect += 1,
-                // Числа увеличивают stack
-                num if num.parse::<Value>().is_ok() => stack_effect += 1,
-                // Пользовательские слова
-                word if self.words.contains_key(word) => {
-                    if let Some(def) = self.words.get(word) {
-                        temp.extend(def.iter().rev().cloned());
-                        continue;
+    /// Evaluates a source file, e.g. one written by `save`
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result {
+        let input = fs::read_to_string(path).map_err(|_| ErrorKind::File)?;
+        if self.include_depth == MAX_INCLUDE_DEPTH {
+            return Err(ErrorKind::RecursionLimit.into());
+        }
+        self.include_depth += 1;
+        let result = self.eval(&input);
+        self.include_depth -= 1;
+        result
+    }
+
+    /// Writes the dictionary and memory to a file as Forth source that rebuilds them
+    /// when loaded into a fresh instance. Native words are not saved, they have to be
+    /// registered again before loading.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.snapshot())
+    }
+
+    /// Source written by `save`. Every definition is replayed, including redefined
+    /// ones, since older definitions may still be used by newer words.
+    pub fn snapshot(&self) -> String {
+        let mut lines = Vec::new();
+        // memory allocated by the replayed lines so far
+        let mut here = 0;
+        for definition in &self.definitions {
+            match (definition.source.split(' ').next(), &definition.code[..]) {
+                (Some("native"), _) => continue,
+                (Some("variable"), &[Op::Push(address)]) => {
+                    // keep variables at the same addresses, a negative `allot` may have
+                    // moved `here` back before the variable was created
+                    let address = address.as_i128();
+                    if address != here {
+                        lines.push(format!("{} allot", address - here));
                     }
+                    here = address + 1;
                 }
                 _ => {}
             }
+            lines.push(definition.source.clone());
         }
 
-        if stack_effect == 0 {
-            let mut sim_stack: Vec<String> = Vec::new();
-            for token in definition {
-                match token.as_str() {
-                    "drop" => {
-                        if !sim_stack.is_empty() {
-                            sim_stack.pop();
-                        }
-                    }
-                    "dup" => {
-                        if let Some(top) = sim_stack.last() {
-                            sim_stack.push(top.clone());
-                        }
-                    }
-                    "swap" => {
-                        if sim_stack.len() >= 2 {
-                            let a = sim_stack.pop().unwrap();
-                            let b = sim_stack.pop().unwrap();
-                            sim_stack.push(a);
-                            sim_stack.push(b);
-                        }
-                    }
-                    "over" => {
-                        if sim_stack.len() >= 2 {
-                            let a = sim_stack[sim_stack.len() - 2].clone();
-                            sim_stack.push(a);
-                        }
-                    }
-                    "+" | "-" | "*" | "/" => {
-                        if sim_stack.len() >= 2 {
-                            let _ = sim_stack.pop();
-                            let _ = sim_stack.pop();
-                            sim_stack.push("0".to_string()); // Просто помещаем заглушку
-                        }
-                    }
-                    num if num.parse::<Value>().is_ok() => {
-                        sim_stack.push(token.clone());
+        let size = self.memory.len() as i128;
+        if size != here {
+            lines.push(format!("{} allot", size - here));
+        }
+        for (address, cell) in self.memory.iter().enumerate() {
+            if *cell != C::default() {
+                lines.push(format!("{cell} {address} !"));
+            }
+        }
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    /// Stack effect of the current definition of a word, `None` for unknown words
+    /// and words whose effect depends on the data they get
+    pub fn stack_effect(&self, name: &str) -> Option<StackEffect> {
+        let index = self.words.get(&name.to_lowercase())?;
+        self.definitions[*index].effect
+    }
+
+    /// Names of all visible words: definitions, the most recently defined first,
+    /// then the built-in words they don't shadow
+    pub fn words(&self) -> Vec<&str> {
+        let definitions = self
+            .definitions
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(index, def)| self.words.get(&def.name) == Some(index))
+            .map(|(_, def)| def.name.as_str());
+        let floats: &[&str] = if self.floats.is_some() { &FLOAT_PRIMITIVES } else { &[] };
+        let builtins = PRIMITIVES.iter().chain(floats).chain(&CONTROL_WORDS).chain(&DEFINING_WORDS);
+        let builtins = builtins.copied().filter(|name| !self.words.contains_key(*name));
+        definitions.chain(builtins).collect()
+    }
+
+    /// Whether a word is built into the interpreter and not shadowed by a definition.
+    /// `see` has no source to show for these
+    pub fn is_builtin(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        !self.words.contains_key(&name) && self.words().contains(&name.as_str())
+    }
+
+    /// Source of the current definition of a word
+    pub fn see(&self, name: &str) -> Option<&str> {
+        let index = self.words.get(&name.to_lowercase())?;
+        Some(&self.definitions[*index].source)
+    }
+
+    /// Splits the input into lowercase words. `." text"` becomes a single token
+    /// that keeps the text as is, including the closing quote if there is one.
+    fn tokenize(input: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut line = 1;
+        let mut offset = 0;
+        while let Some(skip) = input[offset..].find(|c: char| !c.is_whitespace()) {
+            let start = offset + skip;
+            line += input[offset..start].matches('\n').count();
+            let mut end = input[start..]
+                .find(char::is_whitespace)
+                .map_or(input.len(), |e| start + e);
+            let mut text = input[start..end].to_lowercase();
+
+            if text == ".\""
+                && let Some(delimiter) = input[end..].chars().next()
+            {
+                let from = end + delimiter.len_utf8();
+                end = input[from..].find('"').map_or(input.len(), |q| from + q + 1);
+                text = format!(".\"{}", &input[start + 2..end]);
+            }
+
+            tokens.push(Token { text, position: Position { line, offset: start }, end });
+            // only strings can span several lines
+            line += input[start..end].matches('\n').count();
+            offset = end;
+        }
+        tokens
+    }
+
+    /// Definition of a variable or a constant
+    fn literal(code: Vec<Op<C>>, name: &str) -> Definition<C> {
+        let definition = Definition::new(code, vec![name.to_string()]);
+        Definition { effect: Some(StackEffect::new(0, 1)), ..definition }
+    }
+
+    fn define(&mut self, definition: Definition<C>) {
+        self.words.insert(definition.name.clone(), self.definitions.len());
+        self.definitions.push(definition);
+    }
+
+    fn check_name(&self, name: &str) -> Step {
+        let is_number = name.parse::<C>().is_ok() || self.parse_float(name).is_some();
+        if is_number || CONTROL_WORDS.contains(&name) {
+            return Err(ErrorKind::InvalidWord);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, tokens: &[Token]) -> Result {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let (definition, origins) = self.compile(tokens, None)?;
+        let positions: Vec<Position> = origins.iter().map(|&i| tokens[i].position).collect();
+
+        self.definitions.push(definition);
+        let result = self.execute(self.definitions.len() - 1, &positions);
+        self.definitions.pop();
+        result
+    }
+
+    /// Returns an unnamed definition and, for every op, the index of the token it came from.
+    /// `current` is the index the definition will get if it is a named one, for `RECURSE`.
+    fn compile(
+        &self,
+        tokens: &[Token],
+        current: Option<usize>,
+    ) -> std::result::Result<(Definition<C>, Vec<usize>), Error> {
+        let mut code = Vec::new();
+        let mut origins = Vec::new();
+        let mut strings = Vec::new();
+        // (word, position to patch or jump back to, position of the enclosing BEGIN for WHILE)
+        let mut open: Vec<(&Token, usize, usize)> = Vec::new();
+
+        for (origin, token) in tokens.iter().enumerate() {
+            let mut emit = |op| {
+                code.push(op);
+                origins.push(origin);
+                code.len() - 1
+            };
+            let expected: &[&str] = match token.text.as_str() {
+                "if" => {
+                    open.push((token, emit(Op::JumpIfZero(0)), 0));
+                    continue;
+                }
+                "do" => {
+                    open.push((token, emit(Op::Do) + 1, 0));
+                    continue;
+                }
+                "begin" => {
+                    open.push((token, code.len(), 0));
+                    continue;
+                }
+                "recurse" | "exit" => {
+                    let current =
+                        current.ok_or_else(|| Error::at(ErrorKind::MisplacedControl, token))?;
+                    emit(if token.text == "exit" { Op::Exit } else { Op::Call(current) });
+                    continue;
+                }
+                "else" => &["if"],
+                "then" => &["if", "else"],
+                "loop" => &["do"],
+                "until" | "while" => &["begin"],
+                "repeat" => &["while"],
+                word if word == ".\"" || word.starts_with(".\" ") => {
+                    let text = Self::string_literal(word)
+                        .ok_or_else(|| Error::at(ErrorKind::UnterminatedString, token))?;
+                    strings.push(text.to_string());
+                    emit(Op::PrintString(strings.len() - 1));
+                    continue;
+                }
+                word => {
+                    let op = self.compile_word(word).map_err(|kind| Error::at(kind, token))?;
+                    if let Some(op) = op {
+                        emit(op);
                     }
-                    word if self.words.contains_key(word) => {
-                        if let Some(def) = self.words.get(word) {
-                            for t in def {
-                                sim_stack.push(t.clone());
-                            }
-                        }
+                    continue;
+                }
+            };
+
+            let (opening, at, begin) = open
+                .pop()
+                .ok_or_else(|| Error::at(ErrorKind::UnbalancedControl, token))?;
+            if !expected.contains(&opening.text.as_str()) {
+                return Err(Error::at(ErrorKind::MisplacedControl, token));
+            }
+            match token.text.as_str() {
+                "else" => {
+                    let jump = emit(Op::Jump(0));
+                    code[at] = Op::JumpIfZero(code.len());
+                    open.push((token, jump, 0));
+                }
+                "then" => {
+                    code[at] = match code[at] {
+                        Op::Jump(_) => Op::Jump(code.len()),
+                        _ => Op::JumpIfZero(code.len()),
                     }
-                    _ => {}
+                }
+                "loop" => {
+                    emit(Op::Loop(at));
+                }
+                "until" => {
+                    emit(Op::JumpIfZero(at));
+                }
+                "while" => open.push((token, emit(Op::JumpIfZero(0)), at)),
+                _ => {
+                    emit(Op::Jump(begin));
+                    code[at] = Op::JumpIfZero(code.len());
                 }
             }
+        }
 
-            // Если в итоге stack не изменился - это вырожденное выражение
-            if sim_stack.is_empty() {
-                return Vec::new();
-            }
+        if let Some((opening, _, _)) = open.pop() {
+            return Err(Error::at(ErrorKind::UnbalancedControl, opening));
         }
+        let words = origins.iter().map(|&i| tokens[i].text.clone()).collect();
+        Ok((Definition { strings, ..Definition::new(code, words) }, origins))
+    }
 
-        definition.to_vec()
+    /// Text of a `." text"` token, `None` if the closing quote is missing
+    fn string_literal(token: &str) -> Option<&str> {
+        let mut rest = token.strip_prefix(".\"")?.chars();
+        rest.next().filter(|c| c.is_whitespace())?;
+        rest.as_str().strip_suffix('"')
     }
 
-    fn eval_token(&mut self, token: &str) -> Result {
-        match token {
-            "+" => self.add(),
-            "-" => self.sub(),
-            "*" => self.mul(),
-            "/" => self.div(),
-            "dup" => self.dup(),
-            "drop" => self.drop(),
-            "swap" => self.swap(),
-            "over" => self.over(),
-            _ => {
-                if let Ok(num) = token.parse::<Value>() {
-                    self.stack.push(num);
-                    Ok(())
-                } else {
-                    Err(Error::UnknownWord)
-                }
+    /// Calls to empty definitions compile to nothing, host words are called directly
+    fn compile_word(&self, word: &str) -> Step<Option<Op<C>>> {
+        if let Some(&index) = self.words.get(word) {
+            return Ok(match self.definitions[index].code[..] {
+                [] => None,
+                [Op::Native(native)] => Some(Op::Native(native)),
+                _ => Some(Op::Call(index)),
+            });
+        }
+        if let Ok(num) = word.parse::<C>() {
+            return Ok(Some(Op::Push(num)));
+        }
+        if let Some(op) = self.compile_float_word(word) {
+            return Ok(Some(op));
+        }
+        Ok(Some(match word {
+            "+" => Op::Add,
+            "-" => Op::Sub,
+            "*" => Op::Mul,
+            "/" => Op::Div,
+            "dup" => Op::Dup,
+            "drop" => Op::Drop,
+            "swap" => Op::Swap,
+            "over" => Op::Over,
+            "=" => Op::Eq,
+            "<" => Op::Lt,
+            ">" => Op::Gt,
+            "i" => Op::I,
+            "j" => Op::J,
+            "@" => Op::Fetch,
+            "!" => Op::Store,
+            "+!" => Op::AddStore,
+            "allot" => Op::Allot,
+            "here" => Op::Here,
+            "cells" => Op::Cells,
+            "." => Op::Print,
+            ".s" => Op::PrintStack,
+            "emit" => Op::Emit,
+            "cr" => Op::Cr,
+            "type" => Op::Type,
+            ">r" => Op::ToReturn,
+            "r>" => Op::FromReturn,
+            "r@" => Op::FetchReturn,
+            _ => return Err(ErrorKind::UnknownWord),
+        }))
+    }
+
+    fn compile_float_word(&self, word: &str) -> Option<Op<C>> {
+        self.floats.as_ref()?;
+        if let Some(num) = self.parse_float(word) {
+            return Some(Op::FPush(num));
+        }
+        Some(match word {
+            "f+" => Op::FAdd,
+            "f-" => Op::FSub,
+            "f*" => Op::FMul,
+            "f/" => Op::FDiv,
+            "f." => Op::FPrint,
+            "s>f" => Op::ToFloat,
+            "f>s" => Op::ToCell,
+            _ => return None,
+        })
+    }
+
+    /// Float literals need a decimal point or an exponent, e.g. `1.5` or `2e3`
+    fn parse_float(&self, word: &str) -> Option<f64> {
+        self.floats.as_ref()?;
+        if !word.contains(['.', 'e']) {
+            return None;
+        }
+        word.parse().ok()
+    }
+
+    /// Definitions that leave the stack untouched and can't fail are compiled to nothing
+    fn is_noop(code: &[Op<C>], effect: Option<StackEffect>) -> bool {
+        // arithmetic may overflow, host words may have side effects
+        let pure = code.iter().all(|op| {
+            matches!(
+                op,
+                Op::Push(_) | Op::Dup | Op::Drop | Op::Swap | Op::Over | Op::Eq | Op::Lt | Op::Gt
+            )
+        });
+        pure && effect == Some(StackEffect::new(0, 0))
+    }
+
+    /// Infers the stack effect of a definition by following every path through its code.
+    /// Fails with the index of the op that underflows the return stack on every path
+    /// reaching it, as the data stack of the caller is unknown but its return stack isn't.
+    fn analyze(&self, code: &[Op<C>]) -> std::result::Result<Option<StackEffect>, usize> {
+        // depths at every op and at the end, once reached
+        let mut reached: Vec<Option<Depths>> = vec![None; code.len() + 1];
+        let mut pending = vec![(0, Depths { data: Some(0), returns: Some(0) })];
+        let mut inputs = 0;
+
+        while let Some((pc, depths)) = pending.pop() {
+            let depths = reached[pc].map_or(depths, |old| old.merge(depths));
+            if reached[pc] == Some(depths) {
+                continue;
             }
+            reached[pc] = Some(depths);
+            let Some(&op) = code.get(pc) else { continue };
+
+            let effect = self.op_effect(op);
+            let data = depths.data.zip(effect).map(|(depth, (takes, gives))| {
+                inputs = inputs.max(takes as isize - depth);
+                depth - takes as isize + gives as isize
+            });
+            let returns = match op {
+                Op::ToReturn => depths.returns.map(|depth| depth + 1),
+                Op::FromReturn | Op::FetchReturn if depths.returns == Some(0) => return Err(pc),
+                Op::FromReturn => depths.returns.map(|depth| depth - 1),
+                _ => depths.returns,
+            };
+            let next = Depths { data, returns };
+
+            match op {
+                Op::Jump(to) => pending.push((to, next)),
+                Op::JumpIfZero(to) | Op::Loop(to) => pending.extend([(to, next), (pc + 1, next)]),
+                Op::Exit => pending.push((code.len(), next)),
+                _ => pending.push((pc + 1, next)),
+            }
+        }
+
+        if reached.iter().flatten().any(|depths| depths.data.is_none()) {
+            return Ok(None);
         }
+        let end = reached[code.len()].and_then(|depths| depths.data);
+        Ok(end.map(|end| StackEffect::new(inputs as usize, (end + inputs) as usize)))
     }
 
-    fn add(&mut self) -> Result {
-        let (a, b) = self.pop_two()?;
-        self.stack.push(a + b);
+    /// (taken, left) values of the data stack, `None` for calls with an unknown effect
+    fn op_effect(&self, op: Op<C>) -> Option<(usize, usize)> {
+        Some(match op {
+            Op::Push(_) | Op::Here | Op::I | Op::J | Op::ToCell => (0, 1),
+            Op::FromReturn | Op::FetchReturn => (0, 1),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Eq | Op::Lt | Op::Gt => (2, 1),
+            Op::Dup => (1, 2),
+            Op::Drop | Op::JumpIfZero(_) | Op::Allot | Op::ToFloat => (1, 0),
+            Op::Print | Op::Emit | Op::ToReturn => (1, 0),
+            Op::Swap => (2, 2),
+            Op::Over => (2, 3),
+            Op::Do | Op::Store | Op::AddStore | Op::Type => (2, 0),
+            Op::Fetch | Op::Cells => (1, 1),
+            Op::Call(index) => {
+                // the definition itself is unknown while it is being compiled
+                let effect = self.definitions.get(index)?.effect?;
+                (effect.inputs, effect.outputs)
+            }
+            Op::Native(index) => {
+                let effect = self.natives[index].effect;
+                (effect.inputs, effect.outputs)
+            }
+            Op::Jump(_) | Op::Loop(_) | Op::Exit | Op::PrintStack | Op::Cr => (0, 0),
+            Op::PrintString(_) => (0, 0),
+            Op::FPush(_) | Op::FAdd | Op::FSub | Op::FMul | Op::FDiv | Op::FPrint => (0, 0),
+        })
+    }
+
+    /// `positions` holds the input position of every op of the entry definition
+    fn execute(&mut self, entry: usize, positions: &[Position]) -> Result {
+        let mut control = Control { frames: Vec::new(), loops: Vec::new(), returns: Vec::new() };
+        control.call(entry);
+
+        while let Some(frame) = control.frames.last_mut() {
+            let Some(&op) = self.definitions[frame.definition].code.get(frame.pc) else {
+                control.exit();
+                continue;
+            };
+            frame.pc += 1;
+            let top = control.frames.len() - 1;
+
+            if let Err(kind) = self.step(op, &mut control) {
+                let frames = &control.frames[..=top];
+                let Frame { definition, pc, .. } = frames[top];
+                return Err(Error {
+                    kind,
+                    word: Some(self.definitions[definition].words[pc - 1].clone()),
+                    position: Some(positions[frames[0].pc - 1]),
+                    call_chain: frames[1..]
+                        .iter()
+                        .map(|frame| self.definitions[frame.definition].name.clone())
+                        .collect(),
+                });
+            }
+        }
+
         Ok(())
     }
 
-    fn sub(&mut self) -> Result {
-        let (a, b) = self.pop_two()?;
-        self.stack.push(a - b);
+    fn step(&mut self, op: Op<C>, control: &mut Control<C>) -> Step {
+        let frame = control.frames.last_mut().expect("a definition is being executed");
+        let loops = &mut control.loops;
+        match op {
+            Op::Push(num) => self.stack.push(num),
+            Op::Add => self.arithmetic(C::checked_add)?,
+            Op::Sub => self.arithmetic(C::checked_sub)?,
+            Op::Mul => self.arithmetic(C::checked_mul)?,
+            Op::Div => self.div()?,
+            Op::Dup => self.dup()?,
+            Op::Drop => self.drop()?,
+            Op::Swap => self.swap()?,
+            Op::Over => self.over()?,
+            Op::Eq => self.compare(|a, b| a == b)?,
+            Op::Lt => self.compare(|a, b| a < b)?,
+            Op::Gt => self.compare(|a, b| a > b)?,
+            Op::Call(index) => {
+                if control.frames.len() > self.max_call_depth.unwrap_or(MAX_CALL_DEPTH) {
+                    return Err(ErrorKind::RecursionLimit);
+                }
+                control.call(index);
+            }
+            Op::Exit => control.exit(),
+            Op::Jump(to) => frame.pc = to,
+            Op::JumpIfZero(to) => {
+                if self.pop()? == C::default() {
+                    frame.pc = to;
+                }
+            }
+            Op::Do => {
+                let (limit, start) = self.pop_two()?;
+                loops.push((start, limit));
+            }
+            Op::Loop(to) => {
+                let (index, limit) = loops.last_mut().ok_or(ErrorKind::MisplacedControl)?;
+                let one = C::from_i128(1).unwrap();
+                *index = index.checked_add(one).ok_or(ErrorKind::Overflow)?;
+                if *index < *limit {
+                    frame.pc = to;
+                } else {
+                    loops.pop();
+                }
+            }
+            Op::I => {
+                let (index, _) = loops.last().ok_or(ErrorKind::MisplacedControl)?;
+                self.stack.push(*index);
+            }
+            Op::J => {
+                let depth = loops.len().checked_sub(2).ok_or(ErrorKind::MisplacedControl)?;
+                self.stack.push(loops[depth].0);
+            }
+            Op::Fetch => {
+                let address = self.address()?;
+                self.stack.push(self.memory[address]);
+            }
+            Op::Store => {
+                let address = self.address()?;
+                self.memory[address] = self.pop()?;
+            }
+            Op::AddStore => {
+                let address = self.address()?;
+                let sum = self.memory[address].checked_add(self.pop()?);
+                self.memory[address] = sum.ok_or(ErrorKind::Overflow)?;
+            }
+            Op::Allot => {
+                let cells = self.pop()?;
+                self.allot(cells.as_i128())?;
+            }
+            Op::Here => self.stack.push(self.here()?),
+            // memory is addressed in cells, so n CELLS is just n
+            Op::Cells => {
+                let n = self.pop()?;
+                self.stack.push(n);
+            }
+            Op::Native(index) => {
+                let native = &mut self.natives[index];
+                let depth = self.stack.len();
+                if depth < native.effect.inputs {
+                    return Err(ErrorKind::StackUnderflow);
+                }
+                (native.func)(&mut self.stack)?;
+                if self.stack.len() + native.effect.inputs != depth + native.effect.outputs {
+                    return Err(ErrorKind::StackEffectMismatch);
+                }
+            }
+            Op::FPush(num) => self.floats()?.push(num),
+            Op::FAdd => self.float_arithmetic(|a, b| a + b)?,
+            Op::FSub => self.float_arithmetic(|a, b| a - b)?,
+            Op::FMul => self.float_arithmetic(|a, b| a * b)?,
+            Op::FDiv => self.float_arithmetic(|a, b| a / b)?,
+            Op::FPrint => {
+                let num = self.float_pop()?;
+                self.output.write(&format!("{num} "))?;
+            }
+            Op::ToFloat => {
+                let n = self.pop()?;
+                self.floats()?.push(n.as_i128() as f64);
+            }
+            Op::ToCell => {
+                let num = self.float_pop()?;
+                let n = Some(num).filter(|n| n.is_finite()).and_then(|n| C::from_i128(n as i128));
+                self.stack.push(n.ok_or(ErrorKind::Overflow)?);
+            }
+            Op::Print => {
+                let n = self.pop()?;
+                self.output.write(&format!("{n} "))?;
+            }
+            Op::PrintStack => {
+                let mut text = format!("<{}> ", self.stack.len());
+                self.stack.iter().for_each(|n| text += &format!("{n} "));
+                self.output.write(&text)?;
+            }
+            Op::Emit => {
+                let c = Self::char(self.pop()?)?;
+                self.output.write(c.encode_utf8(&mut [0; 4]))?;
+            }
+            Op::Cr => self.output.write("\n")?,
+            Op::Type => {
+                let (address, count) = self.pop_two()?;
+                let range = usize::try_from(address.as_i128())
+                    .ok()
+                    .zip(usize::try_from(count.as_i128()).ok())
+                    .and_then(|(start, count)| Some(start..start.checked_add(count)?));
+                let cells = range
+                    .and_then(|range| self.memory.get(range))
+                    .ok_or(ErrorKind::InvalidAddress)?;
+                let text = cells.iter().map(|&c| Self::char(c)).collect::<Step<String>>()?;
+                self.output.write(&text)?;
+            }
+            Op::PrintString(index) => {
+                self.output.write(&self.definitions[frame.definition].strings[index])?;
+            }
+            Op::ToReturn => {
+                let n = self.pop()?;
+                control.returns.push(n);
+            }
+            // values moved by the callers are out of reach
+            Op::FromReturn | Op::FetchReturn if control.returns.len() == frame.returns => {
+                return Err(ErrorKind::StackUnderflow);
+            }
+            Op::FromReturn => self.stack.extend(control.returns.pop()),
+            Op::FetchReturn => self.stack.extend(control.returns.last()),
+        }
         Ok(())
     }
 
-    fn mul(&mut self) -> Result {
+    fn arithmetic(&mut self, op: fn(C, C) -> Option<C>) -> Step {
         let (a, b) = self.pop_two()?;
-        self.stack.push(a * b);
+        self.stack.push(op(a, b).ok_or(ErrorKind::Overflow)?);
         Ok(())
     }
 
-    fn div(&mut self) -> Result {
+    fn div(&mut self) -> Step {
         let (a, b) = self.pop_two()?;
-        if b == 0 {
-            return Err(Error::DivisionByZero);
+        if b == C::default() {
+            return Err(ErrorKind::DivisionByZero);
         }
-        self.stack.push(a / b);
+        self.stack.push(a.checked_div(b).ok_or(ErrorKind::Overflow)?);
         Ok(())
     }
 
-    fn dup(&mut self) -> Result {
-        let a = self.stack.pop().ok_or(Error::StackUnderflow)?;
+    fn dup(&mut self) -> Step {
+        let a = self.stack.pop().ok_or(ErrorKind::StackUnderflow)?;
         self.stack.push(a);
         self.stack.push(a);
         Ok(())
     }
 
-    fn drop(&mut self) -> Result {
-        self.stack.pop().ok_or(Error::StackUnderflow)?;
+    fn drop(&mut self) -> Step {
+        self.stack.pop().ok_or(ErrorKind::StackUnderflow)?;
         Ok(())
     }
 
-    fn swap(&mut self) -> Result {
+    fn swap(&mut self) -> Step {
         let (a, b) = self.pop_two()?;
         self.stack.push(b);
         self.stack.push(a);
         Ok(())
     }
 
-    fn over(&mut self) -> Result {
+    fn over(&mut self) -> Step {
         let (a, b) = self.pop_two()?;
         self.stack.push(a);
         self.stack.push(b);
@@ -232,20 +1188,79 @@ impl Forth {
         Ok(())
     }
 
-    fn pop_two(&mut self) -> std::result::Result<(Value, Value), Error> {
-        let b = self.stack.pop().ok_or(Error::StackUnderflow)?;
-        let a = self.stack.pop().ok_or(Error::StackUnderflow)?;
-        Ok((a, b))
+    fn here(&self) -> Step<C> {
+        C::from_i128(self.memory.len() as i128).ok_or(ErrorKind::Overflow)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn allot(&mut self, cells: i128) -> Step {
+        // Only a positive `cells` can overflow, and that much memory is never available
+        let size = (self.memory.len() as i128).checked_add(cells).ok_or(ErrorKind::OutOfMemory)?;
+        if size < 0 {
+            return Err(ErrorKind::InvalidAddress);
+        }
+        let size = usize::try_from(size)
+            .ok()
+            .filter(|&size| size <= MEMORY_LIMIT)
+            .ok_or(ErrorKind::OutOfMemory)?;
+        self.memory.resize(size, C::default());
+        Ok(())
+    }
 
-    #[test]
-    fn check() {
-        let mut f = Forth::new();
+    fn address(&mut self) -> Step<usize> {
+        let address = self.pop()?;
+        usize::try_from(address.as_i128())
+            .ok()
+            .filter(|&a| a < self.memory.len())
+            .ok_or(ErrorKind::InvalidAddress)
+    }
+
+    fn char(code: C) -> Step<char> {
+        u32::try_from(code.as_i128())
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(ErrorKind::InvalidCharacter)
+    }
+
+    fn compare(&mut self, op: fn(C, C) -> bool) -> Step {
+        let (a, b) = self.pop_two()?;
+        let flag = if op(a, b) { -1 } else { 0 };
+        self.stack.push(C::from_i128(flag).unwrap());
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Step<C> {
+        self.stack.pop().ok_or(ErrorKind::StackUnderflow)
+    }
+
+    fn pop_two(&mut self) -> Step<(C, C)> {
+        let b = self.stack.pop().ok_or(ErrorKind::StackUnderflow)?;
+        let a = self.stack.pop().ok_or(ErrorKind::StackUnderflow)?;
+        Ok((a, b))
+    }
+
+    fn floats(&mut self) -> Step<&mut Vec<f64>> {
+        self.floats.as_mut().ok_or(ErrorKind::UnknownWord)
+    }
+
+    fn float_pop(&mut self) -> Step<f64> {
+        self.floats()?.pop().ok_or(ErrorKind::StackUnderflow)
+    }
+
+    fn float_arithmetic(&mut self, op: fn(f64, f64) -> f64) -> Step {
+        let b = self.float_pop()?;
+        let a = self.float_pop()?;
+        self.floats()?.push(op(a, b));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let mut f = Forth::new();
         f.eval(": a 0 drop ;").unwrap();
         f.eval(": b a a ;").unwrap();
         f.eval(": c b b ;").unwrap();
@@ -275,428 +1290,1525 @@ mod tests {
         assert!(f.stack().is_empty());
     }
 
-    mod parsing_and_numbers {
+    mod parsing_and_numbers {
+        use super::super::*;
+
+        #[test]
+        fn numbers_just_get_pushed_onto_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 3 4 5").is_ok());
+            assert_eq!(f.stack(), [1, 2, 3, 4, 5]);
+        }
+
+        #[test]
+        fn pushes_negative_numbers_onto_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("-1 -2 -3 -4 -5").is_ok());
+            assert_eq!(f.stack(), [-1, -2, -3, -4, -5]);
+        }
+    }
+
+    mod addition {
+        use super::super::*;
+
+        #[test]
+        fn can_add_two_numbers() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 +").is_ok());
+            assert_eq!(f.stack(), [3]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("+").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_there_is_only_one_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 +").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn more_than_two_values_on_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 3 +").is_ok());
+            assert_eq!(f.stack(), [1, 5]);
+        }
+    }
+
+    mod subtraction {
+        use super::super::*;
+
+        #[test]
+        fn can_subtract_two_numbers() {
+            let mut f = Forth::new();
+            assert!(f.eval("3 4 -").is_ok());
+            assert_eq!(f.stack(), [-1]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("-").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_there_is_only_one_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 -").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn more_than_two_values_on_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 12 3 -").is_ok());
+            assert_eq!(f.stack(), [1, 9]);
+        }
+    }
+
+    mod multiplication {
+        use super::super::*;
+        #[test]
+        fn can_multiply_two_numbers() {
+            let mut f = Forth::new();
+            assert!(f.eval("2 4 *").is_ok());
+            assert_eq!(f.stack(), [8]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("*").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_there_is_only_one_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 *").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn more_than_two_values_on_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 3 *").is_ok());
+            assert_eq!(f.stack(), [1, 6]);
+        }
+    }
+
+    mod division {
+        use super::super::*;
+        #[test]
+        fn can_divide_two_numbers() {
+            let mut f = Forth::new();
+            assert!(f.eval("12 3 /").is_ok());
+            assert_eq!(f.stack(), [4]);
+        }
+
+        #[test]
+        fn performs_integer_division() {
+            let mut f = Forth::new();
+            assert!(f.eval("8 3 /").is_ok());
+            assert_eq!(f.stack(), [2]);
+        }
+
+        #[test]
+        fn errors_if_dividing_by_zero() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("4 0 /").unwrap_err(), ErrorKind::DivisionByZero);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("/").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_there_is_only_one_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 /").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn more_than_two_values_on_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 12 3 /").is_ok());
+            assert_eq!(f.stack(), [1, 4]);
+        }
+    }
+
+    mod combined_arithmetic {
+        use super::super::*;
+        #[test]
+        fn addition_and_subtraction() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 + 4 -").is_ok());
+            assert_eq!(f.stack(), [-1]);
+        }
+
+        #[test]
+        fn multiplication_and_division() {
+            let mut f = Forth::new();
+            assert!(f.eval("2 4 * 3 /").is_ok());
+            assert_eq!(f.stack(), [2]);
+        }
+
+        #[test]
+        fn multiplication_and_addition() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 3 4 * +").is_ok());
+            assert_eq!(f.stack(), [13]);
+        }
+
+        #[test]
+        fn addition_and_multiplication() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 3 4 + *").is_ok());
+            assert_eq!(f.stack(), [7]);
+        }
+    }
+
+    mod dup {
+        use super::super::*;
+        #[test]
+        fn copies_a_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 dup").is_ok());
+            assert_eq!(f.stack(), [1, 1]);
+        }
+
+        #[test]
+        fn copies_the_top_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 dup").is_ok());
+            assert_eq!(f.stack(), [1, 2, 2]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("dup").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+    }
+
+    mod drop {
+        use super::super::*;
+        #[test]
+        fn removes_the_top_value_on_the_stack_if_it_is_the_only_one() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 drop").is_ok());
+            assert_eq!(f.stack(), &[] as &[Value]);
+            assert!(f.stack().is_empty());
+        }
+
+        #[test]
+        fn removes_the_top_value_on_the_stack_if_it_is_not_the_only_one() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 drop").is_ok());
+            assert_eq!(f.stack(), [1]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("drop").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+    }
+
+    mod swap {
+        use super::super::*;
+        #[test]
+        fn swaps_the_top_two_values_on_the_stack_if_they_are_the_only_ones() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 swap").is_ok());
+            assert_eq!(f.stack(), [2, 1]);
+        }
+
+        #[test]
+        fn swaps_the_top_two_values_on_the_stack_if_they_are_not_the_only_ones() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 3 swap").is_ok());
+            assert_eq!(f.stack(), [1, 3, 2]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("swap").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_there_is_only_one_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 swap").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+    }
+
+    mod over {
+        use super::super::*;
+        #[test]
+        fn copies_the_second_element_if_there_are_only_two() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 over").is_ok());
+            assert_eq!(f.stack(), [1, 2, 1]);
+        }
+
+        #[test]
+        fn copies_the_second_element_if_there_are_more_than_two() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 3 over").is_ok());
+            assert_eq!(f.stack(), [1, 2, 3, 2]);
+        }
+
+        #[test]
+        fn errors_if_there_is_nothing_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("over").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_there_is_only_one_value_on_the_stack() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 over").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+    }
+
+    mod user_defined_words {
+        use super::super::*;
+        #[test]
+        fn can_consist_of_built_in_words() {
+            let mut f = Forth::new();
+            assert!(f.eval(": dup-twice dup dup ;").is_ok());
+            assert!(f.eval("1 dup-twice").is_ok());
+            assert_eq!(f.stack(), [1, 1, 1]);
+        }
+
+        #[test]
+        fn execute_in_the_right_order() {
+            let mut f = Forth::new();
+            assert!(f.eval(": countup 1 2 3 ;").is_ok());
+            assert!(f.eval("countup").is_ok());
+            assert_eq!(f.stack(), [1, 2, 3]);
+        }
+
+        #[test]
+        fn can_override_other_user_defined_words() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo dup ;").is_ok());
+            assert!(f.eval(": foo dup dup ;").is_ok());
+            assert!(f.eval("1 foo").is_ok());
+            assert_eq!(f.stack(), [1, 1, 1]);
+        }
+
+        #[test]
+        fn can_override_built_in_words() {
+            let mut f = Forth::new();
+            assert!(f.eval(": swap dup ;").is_ok());
+            assert!(f.eval("1 swap").is_ok());
+            assert_eq!(f.stack(), [1, 1]);
+        }
+
+        #[test]
+        fn can_override_built_in_operators() {
+            let mut f = Forth::new();
+            assert!(f.eval(": + * ;").is_ok());
+            assert!(f.eval("3 4 +").is_ok());
+            assert_eq!(f.stack(), [12]);
+        }
+
+        #[test]
+        fn can_use_different_words_with_the_same_name() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo 5 ;").is_ok());
+            assert!(f.eval(": bar foo ;").is_ok());
+            assert!(f.eval(": foo 6 ;").is_ok());
+            assert!(f.eval("bar foo").is_ok());
+            assert_eq!(f.stack(), [5, 6]);
+        }
+
+        #[test]
+        fn can_define_word_that_uses_word_with_the_same_name() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo 10 ;").is_ok());
+            assert!(f.eval(": foo foo 1 + ;").is_ok());
+            assert!(f.eval("foo").is_ok());
+            assert_eq!(f.stack(), [11]);
+        }
+
+        #[test]
+        fn cannot_redefine_non_negative_numbers() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval(": 1 2 ;").unwrap_err(), ErrorKind::InvalidWord);
+        }
+
+        #[test]
+        fn cannot_redefine_negative_numbers() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval(": -1 2 ;").unwrap_err(), ErrorKind::InvalidWord);
+        }
+
+        #[test]
+        fn errors_if_executing_a_non_existent_word() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("foo").unwrap_err(), ErrorKind::UnknownWord);
+        }
+
+        #[test]
+        fn only_defines_locally() {
+            let mut f = Forth::new();
+            assert!(f.eval(": + - ;").is_ok());
+            assert!(f.eval("1 1 +").is_ok());
+            assert_eq!(f.stack(), [0]);
+            let mut f = Forth::new();
+            assert!(f.eval("1 1 +").is_ok());
+            assert_eq!(f.stack(), [2]);
+        }
+    }
+
+    mod case_insensitivity {
+        use super::super::*;
+
+        #[test]
+        fn dup_is_case_insensitive() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 DUP Dup dup").is_ok());
+            assert_eq!(f.stack(), [1, 1, 1, 1]);
+        }
+
+        #[test]
+        fn drop_is_case_insensitive() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 3 4 DROP Drop drop").is_ok());
+            assert_eq!(f.stack(), [1]);
+        }
+
+        #[test]
+        fn swap_is_case_insensitive() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 SWAP 3 Swap 4 swap").is_ok());
+            assert_eq!(f.stack(), [2, 3, 4, 1]);
+        }
+
+        #[test]
+        fn over_is_case_insensitive() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 OVER Over over").is_ok());
+            assert_eq!(f.stack(), [1, 2, 1, 2, 1]);
+        }
+
+        #[test]
+        fn user_defined_words_are_case_insensitive() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo dup ;").is_ok());
+            assert!(f.eval("1 FOO Foo foo").is_ok());
+            assert_eq!(f.stack(), [1, 1, 1, 1]);
+        }
+
+        #[test]
+        fn definitions_are_case_insensitive() {
+            let mut f = Forth::new();
+            assert!(f.eval(": SWAP DUP Dup dup ;").is_ok());
+            assert!(f.eval("1 swap").is_ok());
+            assert_eq!(f.stack(), [1, 1, 1, 1]);
+        }
+    }
+
+    mod control_flow {
+        use super::super::*;
+
+        #[test]
+        fn if_then_runs_body_on_true() {
+            let mut f = Forth::new();
+            assert!(f.eval("1 2 = 5 4 > if 10 then").is_ok());
+            assert_eq!(f.stack(), [0, 10]);
+        }
+
+        #[test]
+        fn if_then_skips_body_on_false() {
+            let mut f = Forth::new();
+            assert!(f.eval("7 0 if 10 then").is_ok());
+            assert_eq!(f.stack(), [7]);
+        }
+
+        #[test]
+        fn if_else_then_picks_a_branch() {
+            let mut f = Forth::new();
+            assert!(f.eval(": sign dup 0 < if drop -1 else 0 > if 1 else 0 then then ;").is_ok());
+            assert!(f.eval("-5 sign 0 sign 9 sign").is_ok());
+            assert_eq!(f.stack(), [-1, 0, 1]);
+        }
+
+        #[test]
+        fn do_loop_counts_with_i() {
+            let mut f = Forth::new();
+            assert!(f.eval("4 0 do i loop").is_ok());
+            assert_eq!(f.stack(), [0, 1, 2, 3]);
+        }
+
+        #[test]
+        fn nested_do_loops_expose_outer_index_as_j() {
+            let mut f = Forth::new();
+            assert!(f.eval(": table 3 1 do 3 1 do j i * loop loop ;").is_ok());
+            assert!(f.eval("table").is_ok());
+            assert_eq!(f.stack(), [1, 2, 2, 4]);
+        }
+
+        #[test]
+        fn begin_until_repeats_until_true() {
+            let mut f = Forth::new();
+            assert!(f.eval(": countdown begin dup 1 - dup 0 = until ;").is_ok());
+            assert!(f.eval("3 countdown").is_ok());
+            assert_eq!(f.stack(), [3, 2, 1, 0]);
+        }
+
+        #[test]
+        fn begin_while_repeat_stops_on_false() {
+            let mut f = Forth::new();
+            assert!(f.eval(": halve begin dup 1 > while 2 / repeat ;").is_ok());
+            assert!(f.eval("20 halve 0 halve").is_ok());
+            assert_eq!(f.stack(), [1, 0]);
+        }
+
+        #[test]
+        fn words_with_control_flow_can_be_used_in_other_words() {
+            let mut f = Forth::new();
+            assert!(f.eval(": abs dup 0 < if -1 * then ;").is_ok());
+            assert!(f.eval(": sum-abs 0 swap 0 do swap abs + loop ;").is_ok());
+            assert!(f.eval("-1 2 -3 3 sum-abs").is_ok());
+            assert_eq!(f.stack(), [6]);
+        }
+
+        #[test]
+        fn conditional_no_op_definitions_are_kept() {
+            let mut f = Forth::new();
+            assert!(f.eval(": maybe-drop if drop then ;").is_ok());
+            assert!(f.eval("5 1 maybe-drop 6 0 maybe-drop").is_ok());
+            assert_eq!(f.stack(), [6]);
+        }
+
+        #[test]
+        fn errors_if_condition_is_missing() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("if 1 then").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_on_then_without_if() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 then").unwrap_err(), ErrorKind::UnbalancedControl);
+        }
+
+        #[test]
+        fn errors_on_unterminated_definition_body() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval(": foo begin 1 ;").unwrap_err(), ErrorKind::UnbalancedControl);
+            assert_eq!(f.eval("foo").unwrap_err(), ErrorKind::UnknownWord);
+        }
+
+        #[test]
+        fn errors_on_mismatched_control_words() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("3 0 do 1 if then then").unwrap_err(), ErrorKind::MisplacedControl);
+            assert_eq!(f.eval(": foo 1 if loop ;").unwrap_err(), ErrorKind::MisplacedControl);
+        }
+
+        #[test]
+        fn errors_on_loop_index_outside_of_loop() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("i").unwrap_err(), ErrorKind::MisplacedControl);
+            assert_eq!(f.eval("2 0 do j loop").unwrap_err(), ErrorKind::MisplacedControl);
+        }
+
+        #[test]
+        fn cannot_redefine_control_words() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval(": then 1 ;").unwrap_err(), ErrorKind::InvalidWord);
+        }
+    }
+
+    mod compilation {
+        use super::super::*;
+
+        #[test]
+        fn definitions_are_compiled_to_ops() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo 1 2 + ;").is_ok());
+            assert!(f.eval(": bar foo foo ;").is_ok());
+            assert_eq!(f.definitions[f.words["foo"]].code, [Op::Push(1), Op::Push(2), Op::Add]);
+            assert_eq!(f.definitions[f.words["bar"]].code, [Op::Call(0), Op::Call(0)]);
+        }
+
+        #[test]
+        fn redefinition_keeps_old_body_for_existing_callers() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo 5 ;").is_ok());
+            assert!(f.eval(": bar foo ;").is_ok());
+            assert!(f.eval(": foo 6 ;").is_ok());
+            assert_eq!(f.definitions[f.words["bar"]].code, [Op::Call(0)]);
+            assert_eq!(f.definitions[f.words["foo"]].code, [Op::Push(6)]);
+        }
+
+        #[test]
+        fn errors_on_unknown_word_in_definition() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval(": foo bar ;").unwrap_err(), ErrorKind::UnknownWord);
+            assert_eq!(f.eval("foo").unwrap_err(), ErrorKind::UnknownWord);
+        }
+
+        #[test]
+        fn deeply_nested_calls_run_without_expansion() {
+            let mut f = Forth::new();
+            assert!(f.eval(": a 1 + ;").is_ok());
+            let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+            for pair in names.windows(2) {
+                assert!(f.eval(&format!(": {} {} {} ;", pair[1], pair[0], pair[0])).is_ok());
+            }
+            assert!(f.eval("0 h").is_ok());
+            assert_eq!(f.stack(), [128]);
+        }
+    }
+
+    mod memory {
+        use super::super::*;
+
+        #[test]
+        fn variables_store_and_fetch_values() {
+            let mut f = Forth::new();
+            assert!(f.eval("variable x 42 x ! x @").is_ok());
+            assert_eq!(f.stack(), [42]);
+        }
+
+        #[test]
+        fn variables_keep_their_value_between_evals() {
+            let mut f = Forth::new();
+            assert!(f.eval("variable counter").is_ok());
+            assert!(f.eval(": bump 1 counter +! ;").is_ok());
+            assert!(f.eval("bump bump").is_ok());
+            assert!(f.eval("bump counter @").is_ok());
+            assert_eq!(f.stack(), [3]);
+        }
+
+        #[test]
+        fn variables_start_at_zero() {
+            let mut f = Forth::new();
+            assert!(f.eval("variable x x @").is_ok());
+            assert_eq!(f.stack(), [0]);
+        }
+
+        #[test]
+        fn constants_push_their_value() {
+            let mut f = Forth::new();
+            assert!(f.eval("6 7 * constant answer").is_ok());
+            assert!(f.stack().is_empty());
+            assert!(f.eval("answer ANSWER +").is_ok());
+            assert_eq!(f.stack(), [84]);
+        }
+
+        #[test]
+        fn allot_reserves_cells_after_here() {
+            let mut f = Forth::new();
+            assert!(f.eval("variable arr 2 cells allot here").is_ok());
+            assert_eq!(f.stack(), [3]);
+            assert!(f.eval("drop 10 arr 2 cells + ! arr 2 + @").is_ok());
+            assert_eq!(f.stack(), [10]);
+        }
+
+        #[test]
+        fn memory_can_be_filled_in_a_loop() {
+            let mut f = Forth::new();
+            assert!(f.eval("here constant squares 5 allot").is_ok());
+            assert!(f.eval("5 0 do i i * squares i + ! loop").is_ok());
+            assert!(f.eval("squares 4 + @ squares 2 + @").is_ok());
+            assert_eq!(f.stack(), [16, 4]);
+        }
+
+        #[test]
+        fn errors_if_constant_has_no_value() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("constant x").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_on_access_outside_of_memory() {
+            let mut f = Forth::new();
+            assert!(f.eval("variable x").is_ok());
+            assert_eq!(f.eval("x 1 + @").unwrap_err(), ErrorKind::InvalidAddress);
+            assert_eq!(f.eval("5 -1 !").unwrap_err(), ErrorKind::InvalidAddress);
+        }
+
+        #[test]
+        fn errors_on_freeing_more_than_allotted() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("2 allot -3 allot").unwrap_err(), ErrorKind::InvalidAddress);
+        }
+
+        #[test]
+        fn errors_on_allotting_too_much() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("2000000000 allot").unwrap_err(), ErrorKind::OutOfMemory);
+        }
+
+        #[test]
+        fn cannot_name_variable_as_number() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("variable 5").unwrap_err(), ErrorKind::InvalidWord);
+        }
+    }
+
+    mod native_words {
+        use super::super::*;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[test]
+        fn native_words_operate_on_the_stack() {
+            let mut f = Forth::new();
+            let square = |stack: &mut Vec<Value>| {
+                let n = stack.pop().unwrap();
+                stack.push(n * n);
+                Ok(())
+            };
+            assert!(f.define_native("square", StackEffect::new(1, 1), square).is_ok());
+            assert!(f.eval("3 SQUARE 1 square").is_ok());
+            assert_eq!(f.stack(), [9, 1]);
+        }
+
+        #[test]
+        fn native_words_can_capture_host_state() {
+            let mut f = Forth::new();
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let sink = Rc::clone(&log);
+            let logger = move |stack: &mut Vec<Value>| {
+                sink.borrow_mut().push(stack.pop().unwrap());
+                Ok(())
+            };
+            assert!(f.define_native("log", StackEffect::new(1, 0), logger).is_ok());
+            assert!(f.eval(": log-twice dup log log ;").is_ok());
+            assert!(f.eval("7 log-twice 8 log").is_ok());
+            assert!(f.stack().is_empty());
+            assert_eq!(*log.borrow(), [7, 7, 8]);
+        }
+
+        #[test]
+        fn native_words_without_stack_effect_are_not_optimized_away() {
+            let mut f = Forth::new();
+            let calls = Rc::new(RefCell::new(0));
+            let counter = Rc::clone(&calls);
+            let tick = move |_: &mut Vec<Value>| {
+                *counter.borrow_mut() += 1;
+                Ok(())
+            };
+            assert!(f.define_native("tick", StackEffect::new(0, 0), tick).is_ok());
+            assert!(f.eval(": tock tick ;").is_ok());
+            assert!(f.eval("tock tock").is_ok());
+            assert_eq!(*calls.borrow(), 2);
+        }
+
+        #[test]
+        fn native_words_can_be_overridden() {
+            let mut f = Forth::new();
+            let now = |stack: &mut Vec<Value>| {
+                stack.push(1000);
+                Ok(())
+            };
+            assert!(f.define_native("now", StackEffect::new(0, 1), now).is_ok());
+            assert!(f.eval(": later now 1 + ;").is_ok());
+            assert!(f.eval(": now 0 ;").is_ok());
+            assert!(f.eval("later now").is_ok());
+            assert_eq!(f.stack(), [1001, 0]);
+        }
+
+        #[test]
+        fn native_errors_are_propagated() {
+            let mut f = Forth::new();
+            let fail = |_: &mut Vec<Value>| Err(ErrorKind::DivisionByZero);
+            assert!(f.define_native("fail", StackEffect::new(0, 0), fail).is_ok());
+            assert_eq!(f.eval("fail").unwrap_err(), ErrorKind::DivisionByZero);
+        }
+
+        #[test]
+        fn errors_if_declared_inputs_are_missing() {
+            let mut f = Forth::new();
+            let sum = |stack: &mut Vec<Value>| {
+                let a = stack.pop().unwrap();
+                let b = stack.pop().unwrap();
+                stack.push(a + b);
+                Ok(())
+            };
+            assert!(f.define_native("sum", StackEffect::new(2, 1), sum).is_ok());
+            assert_eq!(f.eval("1 sum").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_if_declared_effect_is_violated() {
+            let mut f = Forth::new();
+            let liar = |stack: &mut Vec<Value>| {
+                stack.push(1);
+                stack.push(2);
+                Ok(())
+            };
+            assert!(f.define_native("liar", StackEffect::new(0, 1), liar).is_ok());
+            assert_eq!(f.eval("liar").unwrap_err(), ErrorKind::StackEffectMismatch);
+        }
+
+        #[test]
+        fn cannot_register_invalid_names() {
+            let mut f = Forth::new();
+            let noop = |_: &mut Vec<Value>| Ok(());
+            for name in ["12", "if", "a b"] {
+                let err = f.define_native(name, StackEffect::new(0, 0), noop).unwrap_err();
+                assert_eq!(err, ErrorKind::InvalidWord);
+            }
+        }
+    }
+
+    mod dictionary {
+        use super::super::*;
+
+        #[test]
+        fn words_lists_visible_words_newest_first() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo 1 ; variable bar : baz 2 ; : foo 3 ;").is_ok());
+            assert_eq!(f.words()[..4], ["foo", "baz", "bar", "+"]);
+        }
+
+        #[test]
+        fn words_lists_builtins_unless_shadowed() {
+            let mut f = Forth::new();
+            assert!(f.words().contains(&"dup"));
+            assert!(f.words().contains(&"if"));
+            assert!(f.words().contains(&"variable"));
+            assert!(!f.words().contains(&"f+"));
+            assert!(f.is_builtin("DUP"));
+            assert!(!f.is_builtin("foo"));
+
+            assert!(f.eval(": dup 1 ;").is_ok());
+            assert_eq!(f.words().iter().filter(|&&word| word == "dup").count(), 1);
+            assert!(!f.is_builtin("dup"));
+            assert!(Forth::new().with_floats().is_builtin("f+"));
+        }
+
+        #[test]
+        fn every_primitive_compiles() {
+            let f = Forth::new().with_floats();
+            for word in PRIMITIVES.iter().chain(&FLOAT_PRIMITIVES) {
+                assert!(f.compile_word(word).is_ok(), "{word}");
+            }
+        }
+
+        #[test]
+        fn see_shows_the_current_definition() {
+            let mut f = Forth::new();
+            assert!(f.eval(": foo 1 ; : FOO foo DUP + ;").is_ok());
+            assert_eq!(f.see("Foo"), Some(": foo foo dup + ;"));
+        }
+
+        #[test]
+        fn see_shows_variables_and_constants() {
+            let mut f = Forth::new();
+            assert!(f.eval("variable x 10 constant ten").is_ok());
+            assert_eq!(f.see("x"), Some("variable x"));
+            assert_eq!(f.see("ten"), Some("10 constant ten"));
+        }
+
+        #[test]
+        fn see_shows_nothing_for_unknown_words() {
+            let f = Forth::new();
+            assert_eq!(f.see("dup"), None);
+        }
+    }
+
+    mod error_reporting {
         use super::super::*;
 
         #[test]
-        fn numbers_just_get_pushed_onto_the_stack() {
+        fn errors_point_at_the_failing_token() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 3 4 5").is_ok());
-            assert_eq!(f.stack(), [1, 2, 3, 4, 5]);
+            let err = f.eval("1 2 +\n3 0 /").unwrap_err();
+            assert_eq!(err.kind, ErrorKind::DivisionByZero);
+            assert_eq!(err.word.as_deref(), Some("/"));
+            assert_eq!(err.position, Some(Position { line: 2, offset: 10 }));
+            assert!(err.call_chain.is_empty());
         }
 
         #[test]
-        fn pushes_negative_numbers_onto_the_stack() {
+        fn errors_inside_definitions_carry_the_call_chain() {
             let mut f = Forth::new();
-            assert!(f.eval("-1 -2 -3 -4 -5").is_ok());
-            assert_eq!(f.stack(), [-1, -2, -3, -4, -5]);
+            assert!(f.eval(": inner 0 / ;").is_ok());
+            assert!(f.eval(": outer 1 inner ;").is_ok());
+            let err = f.eval("5 outer").unwrap_err();
+            assert_eq!(err.kind, ErrorKind::DivisionByZero);
+            assert_eq!(err.word.as_deref(), Some("/"));
+            assert_eq!(err.position, Some(Position { line: 1, offset: 2 }));
+            assert_eq!(err.call_chain, ["outer", "inner"]);
         }
-    }
 
-    mod addition {
-        use super::super::*;
+        #[test]
+        fn compile_errors_point_at_the_offending_word() {
+            let mut f = Forth::new();
+            let err = f.eval(": foo\n  dup BAR ;").unwrap_err();
+            assert_eq!(err.kind, ErrorKind::UnknownWord);
+            assert_eq!(err.word.as_deref(), Some("bar"));
+            assert_eq!(err.position, Some(Position { line: 2, offset: 12 }));
+        }
 
         #[test]
-        fn can_add_two_numbers() {
+        fn unclosed_control_structures_point_at_the_opening_word() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 +").is_ok());
-            assert_eq!(f.stack(), [3]);
+            let err = f.eval("1 if 2 begin 3 until").unwrap_err();
+            assert_eq!(err.kind, ErrorKind::UnbalancedControl);
+            assert_eq!(err.word.as_deref(), Some("if"));
+            assert_eq!(err.position, Some(Position { line: 1, offset: 2 }));
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn errors_can_be_displayed() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("+"), Err(Error::StackUnderflow));
+            assert!(f.eval(": half 2 / ; : halves half half ;").is_ok());
+            let err = f.eval("\nhalves").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "stack underflow in '/' at line 2, offset 1 (inside halves -> half)"
+            );
+            assert_eq!(ErrorKind::UnknownWord.to_string(), "undefined word");
         }
 
         #[test]
-        fn errors_if_there_is_only_one_value_on_the_stack() {
+        fn recursive_calls_are_displayed_once_with_a_count() {
+            let mut f = Forth::new().with_max_call_depth(5);
+            assert!(f.eval(": down dup if 1 - recurse then ; : start down ;").is_ok());
+            let err = f.eval("9 start").unwrap_err();
+            assert_eq!(err.call_chain.len(), 5);
+            assert_eq!(
+                err.to_string(),
+                "recursion depth limit exceeded in 'recurse' at line 1, offset 2 \
+                 (inside start -> down (x4))"
+            );
+
             let mut f = Forth::new();
-            assert_eq!(f.eval("1 +"), Err(Error::StackUnderflow));
+            assert!(f.eval(": inf recurse ;").is_ok());
+            let message = f.eval("inf").unwrap_err().to_string();
+            assert!(message.ends_with("(inside inf (x1000))"), "{message}");
         }
 
         #[test]
-        fn more_than_two_values_on_the_stack() {
+        fn errors_are_std_errors() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 3 +").is_ok());
-            assert_eq!(f.stack(), [1, 5]);
+            let err: Box<dyn std::error::Error> = Box::new(f.eval("drop").unwrap_err());
+            assert_eq!(err.to_string(), "stack underflow in 'drop' at line 1, offset 0");
         }
     }
 
-    mod subtraction {
+    mod cell_types {
         use super::super::*;
 
         #[test]
-        fn can_subtract_two_numbers() {
+        fn errors_on_overflow() {
             let mut f = Forth::new();
-            assert!(f.eval("3 4 -").is_ok());
-            assert_eq!(f.stack(), [-1]);
+            assert_eq!(f.eval("2147483647 1 +").unwrap_err(), ErrorKind::Overflow);
+            assert_eq!(f.eval("-2147483648 1 -").unwrap_err(), ErrorKind::Overflow);
+            assert_eq!(f.eval("65536 65536 *").unwrap_err(), ErrorKind::Overflow);
+            assert_eq!(f.eval("-2147483648 -1 /").unwrap_err(), ErrorKind::Overflow);
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn errors_on_overflow_in_memory() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("-"), Err(Error::StackUnderflow));
+            assert!(f.eval("variable x 2147483647 x !").is_ok());
+            assert_eq!(f.eval("1 x +!").unwrap_err(), ErrorKind::Overflow);
         }
 
         #[test]
-        fn errors_if_there_is_only_one_value_on_the_stack() {
-            let mut f = Forth::new();
-            assert_eq!(f.eval("1 -"), Err(Error::StackUnderflow));
+        fn wider_cells_hold_larger_numbers() {
+            let mut f = Forth::<i64>::default();
+            assert!(f.eval("2147483647 1 + 65536 65536 *").is_ok());
+            assert_eq!(f.stack(), [2147483648, 4294967296]);
+            assert_eq!(f.eval("9223372036854775807 1 +").unwrap_err(), ErrorKind::Overflow);
         }
 
         #[test]
-        fn more_than_two_values_on_the_stack() {
+        fn i128_cells_work_with_every_word() {
+            let mut f = Forth::<i128>::default();
+            assert!(f.eval("variable x 170141183460469231731687303715884105727 x !").is_ok());
+            assert!(f.eval(": count 0 swap 0 do 1 + loop ; 3 count x @ 2 /").is_ok());
+            assert_eq!(f.stack(), [3, 85070591730234615865843651857942052863]);
+        }
+
+        #[test]
+        fn i128_allot_does_not_wrap() {
+            let mut f = Forth::<i128>::default();
+            let max = "170141183460469231731687303715884105727";
+            assert_eq!(
+                f.eval(&format!("variable x {max} allot")).unwrap_err(),
+                ErrorKind::OutOfMemory
+            );
+            assert_eq!(f.eval("18446744073709551716 allot").unwrap_err(), ErrorKind::OutOfMemory);
+            assert!(f.eval("here").is_ok());
+            assert_eq!(f.stack(), [1]);
+        }
+
+        #[test]
+        fn too_large_literals_are_unknown_words() {
             let mut f = Forth::new();
-            assert!(f.eval("1 12 3 -").is_ok());
-            assert_eq!(f.stack(), [1, 9]);
+            assert_eq!(f.eval("2147483648").unwrap_err(), ErrorKind::UnknownWord);
         }
     }
 
-    mod multiplication {
+    mod floats {
         use super::super::*;
+
         #[test]
-        fn can_multiply_two_numbers() {
+        fn float_words_are_unknown_unless_enabled() {
             let mut f = Forth::new();
-            assert!(f.eval("2 4 *").is_ok());
-            assert_eq!(f.stack(), [8]);
+            assert_eq!(f.eval("1.5").unwrap_err(), ErrorKind::UnknownWord);
+            assert_eq!(f.eval("f+").unwrap_err(), ErrorKind::UnknownWord);
+            assert!(f.float_stack().is_empty());
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
-            let mut f = Forth::new();
-            assert_eq!(f.eval("*"), Err(Error::StackUnderflow));
+        fn float_literals_go_to_the_float_stack() {
+            let mut f = Forth::new().with_floats();
+            assert!(f.eval("1 1.5 2e3 -0.25").is_ok());
+            assert_eq!(f.stack(), [1]);
+            assert_eq!(f.float_stack(), [1.5, 2000.0, -0.25]);
         }
 
         #[test]
-        fn errors_if_there_is_only_one_value_on_the_stack() {
-            let mut f = Forth::new();
-            assert_eq!(f.eval("1 *"), Err(Error::StackUnderflow));
+        fn float_arithmetic() {
+            let mut f = Forth::new().with_floats();
+            assert!(f.eval("1.5 2.5 f+ 10.0 f* 4.0 f- 3.0 f/").is_ok());
+            assert_eq!(f.float_stack(), [12.0]);
         }
 
         #[test]
-        fn more_than_two_values_on_the_stack() {
-            let mut f = Forth::new();
-            assert!(f.eval("1 2 3 *").is_ok());
-            assert_eq!(f.stack(), [1, 6]);
+        fn values_move_between_stacks() {
+            let mut f = Forth::new().with_floats();
+            assert!(f.eval("7 s>f 2.0 f/ f>s").is_ok());
+            assert_eq!(f.stack(), [3]);
+            assert!(f.float_stack().is_empty());
+        }
+
+        #[test]
+        fn float_words_can_be_used_in_definitions() {
+            let mut f = Forth::new().with_floats();
+            assert!(f.eval(": half 0.5 f* ;").is_ok());
+            assert!(f.eval("3.0 half half").is_ok());
+            assert_eq!(f.float_stack(), [0.75]);
+        }
+
+        #[test]
+        fn errors_on_float_stack_underflow() {
+            let mut f = Forth::new().with_floats();
+            assert_eq!(f.eval("1 2 f+").unwrap_err(), ErrorKind::StackUnderflow);
+            assert_eq!(f.eval("f.").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn errors_on_converting_out_of_range_floats() {
+            let mut f = Forth::new().with_floats();
+            assert_eq!(f.eval("1e10 f>s").unwrap_err(), ErrorKind::Overflow);
+            assert_eq!(f.eval("1.0 0.0 f/ f>s").unwrap_err(), ErrorKind::Overflow);
+        }
+
+        #[test]
+        fn cannot_name_words_as_float_literals() {
+            let mut f = Forth::new().with_floats();
+            assert_eq!(f.eval(": 1.5 2 ;").unwrap_err(), ErrorKind::InvalidWord);
         }
     }
 
-    mod division {
+    mod output {
         use super::super::*;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
         #[test]
-        fn can_divide_two_numbers() {
+        fn dot_prints_the_top_of_the_stack() {
             let mut f = Forth::new();
-            assert!(f.eval("12 3 /").is_ok());
-            assert_eq!(f.stack(), [4]);
+            assert!(f.eval("1 2 . .").is_ok());
+            assert_eq!(f.take_output(), "2 1 ");
+            assert!(f.stack().is_empty());
         }
 
         #[test]
-        fn performs_integer_division() {
+        fn take_output_clears_the_buffer() {
             let mut f = Forth::new();
-            assert!(f.eval("8 3 /").is_ok());
-            assert_eq!(f.stack(), [2]);
+            assert!(f.eval("1 .").is_ok());
+            assert_eq!(f.take_output(), "1 ");
+            assert!(f.eval("2 .").is_ok());
+            assert_eq!(f.take_output(), "2 ");
         }
 
         #[test]
-        fn errors_if_dividing_by_zero() {
+        fn dot_s_prints_the_whole_stack() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("4 0 /"), Err(Error::DivisionByZero));
+            assert!(f.eval("1 2 3 .s").is_ok());
+            assert_eq!(f.take_output(), "<3> 1 2 3 ");
+            assert_eq!(f.stack(), [1, 2, 3]);
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn emit_and_cr_print_characters() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("/"), Err(Error::StackUnderflow));
+            assert!(f.eval("72 emit 105 emit cr 1064 emit").is_ok());
+            assert_eq!(f.take_output(), "Hi\nШ");
         }
 
         #[test]
-        fn errors_if_there_is_only_one_value_on_the_stack() {
+        fn dot_quote_prints_text_as_is() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("1 /"), Err(Error::StackUnderflow));
+            assert!(f.eval(": greet .\" Hello,  World!\" cr ;").is_ok());
+            assert!(f.eval("greet GREET").is_ok());
+            assert_eq!(f.take_output(), "Hello,  World!\nHello,  World!\n");
+            assert_eq!(f.see("greet"), Some(": greet .\" Hello,  World!\" cr ;"));
         }
 
         #[test]
-        fn more_than_two_values_on_the_stack() {
+        fn dot_quote_text_is_not_interpreted() {
             let mut f = Forth::new();
-            assert!(f.eval("1 12 3 /").is_ok());
-            assert_eq!(f.stack(), [1, 4]);
+            assert!(f.eval(": semi .\" ; : ok\" ;").is_ok());
+            assert!(f.eval("semi .\" \" 1").is_ok());
+            assert_eq!(f.take_output(), "; : ok");
+            assert_eq!(f.stack(), [1]);
         }
-    }
 
-    mod combined_arithmetic {
-        use super::super::*;
         #[test]
-        fn addition_and_subtraction() {
+        fn type_prints_characters_from_memory() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 + 4 -").is_ok());
-            assert_eq!(f.stack(), [-1]);
+            assert!(f.eval("here 79 , 75 ,".replace(" ,", " here 1 allot !").as_str()).is_ok());
+            assert!(f.eval("2 type").is_ok());
+            assert_eq!(f.take_output(), "OK");
         }
 
         #[test]
-        fn multiplication_and_division() {
+        fn output_in_loops() {
             let mut f = Forth::new();
-            assert!(f.eval("2 4 * 3 /").is_ok());
-            assert_eq!(f.stack(), [2]);
+            assert!(f.eval(": stars 0 do 42 emit loop ; 3 stars cr 5 0 do i . loop").is_ok());
+            assert_eq!(f.take_output(), "***\n0 1 2 3 4 ");
         }
 
         #[test]
-        fn multiplication_and_addition() {
-            let mut f = Forth::new();
-            assert!(f.eval("1 3 4 * +").is_ok());
-            assert_eq!(f.stack(), [13]);
+        fn output_goes_to_the_given_writer() {
+            #[derive(Clone, Default)]
+            struct Shared(Rc<RefCell<Vec<u8>>>);
+
+            impl Write for Shared {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    self.0.borrow_mut().write(buf)
+                }
+
+                fn flush(&mut self) -> std::io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let sink = Shared::default();
+            let mut f = Forth::new().with_output(sink.clone());
+            assert!(f.eval(".\" answer: \" 42 .").is_ok());
+            assert_eq!(f.take_output(), "");
+            assert_eq!(*sink.0.borrow(), b"answer: 42 ");
         }
 
         #[test]
-        fn addition_and_multiplication() {
+        fn float_dot_prints_to_the_output() {
+            let mut f = Forth::new().with_floats();
+            assert!(f.eval("2.5 f.").is_ok());
+            assert_eq!(f.take_output(), "2.5 ");
+        }
+
+        #[test]
+        fn errors_on_unterminated_string() {
             let mut f = Forth::new();
-            assert!(f.eval("1 3 4 + *").is_ok());
-            assert_eq!(f.stack(), [7]);
+            assert_eq!(f.eval(".\" no end").unwrap_err(), ErrorKind::UnterminatedString);
+            assert_eq!(f.eval(".\"").unwrap_err(), ErrorKind::UnterminatedString);
         }
-    }
 
-    mod dup {
-        use super::super::*;
         #[test]
-        fn copies_a_value_on_the_stack() {
+        fn errors_on_invalid_characters() {
             let mut f = Forth::new();
-            assert!(f.eval("1 dup").is_ok());
-            assert_eq!(f.stack(), [1, 1]);
+            assert_eq!(f.eval("-1 emit").unwrap_err(), ErrorKind::InvalidCharacter);
+            assert_eq!(f.eval("55296 emit").unwrap_err(), ErrorKind::InvalidCharacter);
         }
 
         #[test]
-        fn copies_the_top_value_on_the_stack() {
+        fn errors_on_typing_outside_of_memory() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 dup").is_ok());
-            assert_eq!(f.stack(), [1, 2, 2]);
+            assert!(f.eval("variable x").is_ok());
+            assert_eq!(f.eval("x 2 type").unwrap_err(), ErrorKind::InvalidAddress);
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn error_positions_account_for_multiline_strings() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("dup"), Err(Error::StackUnderflow));
+            let err = f.eval(".\" one\ntwo\" cr\ndrop").unwrap_err();
+            assert_eq!(err.position, Some(Position { line: 3, offset: 15 }));
         }
     }
 
-    mod drop {
+    mod return_stack {
         use super::super::*;
+
         #[test]
-        fn removes_the_top_value_on_the_stack_if_it_is_the_only_one() {
+        fn values_can_be_moved_to_the_return_stack_and_back() {
             let mut f = Forth::new();
-            assert!(f.eval("1 drop").is_ok());
-            assert_eq!(f.stack(), &[] as &[Value]);
-            assert!(f.stack().is_empty());
+            assert!(f.eval("1 2 >r 3 r@ r> +").is_ok());
+            assert_eq!(f.stack(), [1, 3, 4]);
         }
 
         #[test]
-        fn removes_the_top_value_on_the_stack_if_it_is_not_the_only_one() {
+        fn return_stack_is_usable_in_definitions() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 drop").is_ok());
-            assert_eq!(f.stack(), [1]);
+            assert!(f.eval(": rot >r swap r> swap ;").is_ok());
+            assert!(f.eval("1 2 3 rot").is_ok());
+            assert_eq!(f.stack(), [2, 3, 1]);
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn errors_if_the_return_stack_is_empty() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("drop"), Err(Error::StackUnderflow));
+            assert_eq!(f.eval("r>").unwrap_err(), ErrorKind::StackUnderflow);
+            assert_eq!(f.eval("r@").unwrap_err(), ErrorKind::StackUnderflow);
+            assert_eq!(f.eval(">r").unwrap_err(), ErrorKind::StackUnderflow);
+        }
+
+        #[test]
+        fn values_left_by_a_definition_are_dropped_on_return() {
+            let mut f = Forth::new();
+            assert!(f.eval(": stash >r ;").is_ok());
+            assert_eq!(f.eval("1 stash r>").unwrap_err(), ErrorKind::StackUnderflow);
         }
     }
 
-    mod swap {
+    mod recursion {
         use super::super::*;
+
         #[test]
-        fn swaps_the_top_two_values_on_the_stack_if_they_are_the_only_ones() {
+        fn words_can_call_themselves() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 swap").is_ok());
-            assert_eq!(f.stack(), [2, 1]);
+            assert!(f.eval(": fact dup 1 > if dup 1 - recurse * then ;").is_ok());
+            assert!(f.eval("5 fact 1 fact").is_ok());
+            assert_eq!(f.stack(), [120, 1]);
         }
 
         #[test]
-        fn swaps_the_top_two_values_on_the_stack_if_they_are_not_the_only_ones() {
+        fn recurse_refers_to_the_new_definition() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 3 swap").is_ok());
-            assert_eq!(f.stack(), [1, 3, 2]);
+            assert!(f.eval(": count 1 ;").is_ok());
+            assert!(f.eval(": count dup 0 > if 1 - recurse count + then ;").is_ok());
+            assert!(f.eval("3 count").is_ok());
+            assert_eq!(f.stack(), [3]);
+            assert_eq!(f.see("count"), Some(": count dup 0 > if 1 - recurse count + then ;"));
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn recursion_inside_loops() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("swap"), Err(Error::StackUnderflow));
+            let fib = ": fib dup 2 < if exit then dup 1 - recurse swap 2 - recurse + ;";
+            assert!(f.eval(fib).is_ok());
+            assert!(f.eval(": fibs 0 do i fib loop ; 7 fibs").is_ok());
+            assert_eq!(f.stack(), [0, 1, 1, 2, 3, 5, 8]);
         }
 
         #[test]
-        fn errors_if_there_is_only_one_value_on_the_stack() {
+        fn errors_when_the_call_depth_limit_is_reached() {
+            let mut f = Forth::new().with_max_call_depth(10);
+            assert!(f.eval(": down dup if 1 - recurse then ;").is_ok());
+            assert!(f.eval("9 down").is_ok());
+            let err = f.eval("10 down").unwrap_err();
+            assert_eq!(err, ErrorKind::RecursionLimit);
+            assert_eq!(err.word.as_deref(), Some("recurse"));
+            assert_eq!(err.call_chain.len(), 10);
+        }
+
+        #[test]
+        fn infinite_recursion_is_an_error() {
+            let mut f = Forth::new();
+            assert!(f.eval(": forever recurse ;").is_ok());
+            assert_eq!(f.eval("forever").unwrap_err(), ErrorKind::RecursionLimit);
+        }
+
+        #[test]
+        fn recurse_outside_a_definition_is_an_error() {
+            let mut f = Forth::new();
+            assert_eq!(f.eval("1 recurse").unwrap_err(), ErrorKind::MisplacedControl);
+        }
+
+        #[test]
+        fn cannot_redefine_recurse() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("1 swap"), Err(Error::StackUnderflow));
+            assert_eq!(f.eval(": recurse 1 ;").unwrap_err(), ErrorKind::InvalidWord);
         }
     }
 
-    mod over {
+    mod exit {
         use super::super::*;
+
         #[test]
-        fn copies_the_second_element_if_there_are_only_two() {
+        fn exit_leaves_the_definition() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 over").is_ok());
-            assert_eq!(f.stack(), [1, 2, 1]);
+            assert!(f.eval(": sign dup 0 < if drop -1 exit then 0 > ;").is_ok());
+            assert!(f.eval(": twice sign sign ; -5 sign 0 sign 7 sign -3 twice").is_ok());
+            assert_eq!(f.stack(), [-1, 0, -1, -1]);
         }
 
         #[test]
-        fn copies_the_second_element_if_there_are_more_than_two() {
+        fn exit_from_inside_a_loop() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 3 over").is_ok());
-            assert_eq!(f.stack(), [1, 2, 3, 2]);
+            let first_over = ": first-over 10 0 do dup i < if drop i exit then loop drop -1 ;";
+            assert!(f.eval(first_over).is_ok());
+            assert!(f.eval("3 first-over 20 first-over").is_ok());
+            assert_eq!(f.stack(), [4, -1]);
         }
 
         #[test]
-        fn errors_if_there_is_nothing_on_the_stack() {
+        fn loops_of_the_caller_survive_an_exit() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("over"), Err(Error::StackUnderflow));
+            assert!(f.eval(": inner 5 0 do i 2 = if exit then loop ;").is_ok());
+            assert!(f.eval(": outer 3 0 do inner i loop ; outer").is_ok());
+            assert_eq!(f.stack(), [0, 1, 2]);
         }
 
         #[test]
-        fn errors_if_there_is_only_one_value_on_the_stack() {
+        fn exit_outside_a_definition_is_an_error() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("1 over"), Err(Error::StackUnderflow));
+            assert_eq!(f.eval("exit").unwrap_err(), ErrorKind::MisplacedControl);
         }
     }
 
-    mod user_defined_words {
+    mod snapshots {
         use super::super::*;
+        use std::path::PathBuf;
+
+        /// Temporary file removed when the test is done
+        struct TempFile(PathBuf);
+
+        impl TempFile {
+            fn new(name: &str, content: &str) -> Self {
+                let name = format!("forth-{}-{name}", std::process::id());
+                let path = std::env::temp_dir().join(name);
+                fs::write(&path, content).unwrap();
+                TempFile(path)
+            }
+        }
+
+        impl Drop for TempFile {
+            fn drop(&mut self) {
+                let _ = fs::remove_file(&self.0);
+            }
+        }
+
         #[test]
-        fn can_consist_of_built_in_words() {
+        fn snapshot_is_forth_source() {
             let mut f = Forth::new();
-            assert!(f.eval(": dup-twice dup dup ;").is_ok());
-            assert!(f.eval("1 dup-twice").is_ok());
-            assert_eq!(f.stack(), [1, 1, 1]);
+            assert!(f.eval(": square dup * ; variable x 3 allot").is_ok());
+            assert!(f.eval("variable y 5 constant five").is_ok());
+            assert!(f.eval("7 x ! -1 y !").is_ok());
+            assert_eq!(
+                f.snapshot(),
+                ": square dup * ;\nvariable x\n3 allot\nvariable y\n5 constant five\n\
+                 7 0 !\n-1 4 !\n"
+            );
         }
 
         #[test]
-        fn execute_in_the_right_order() {
+        fn dictionary_survives_a_save_and_load() {
             let mut f = Forth::new();
-            assert!(f.eval(": countup 1 2 3 ;").is_ok());
-            assert!(f.eval("countup").is_ok());
-            assert_eq!(f.stack(), [1, 2, 3]);
+            assert!(f.eval(": foo 5 ; : bar foo ; : foo 6 ; variable counter 10 allot").is_ok());
+            assert!(f.eval("counter 2 cells + dup 42 swap ! counter +!").is_ok());
+            let file = TempFile::new("dictionary", "");
+            f.save(&file.0).unwrap();
+
+            let mut g = Forth::new();
+            assert!(g.load(&file.0).is_ok());
+            assert_eq!(g.words(), f.words());
+            assert_eq!(g.see("foo"), Some(": foo 6 ;"));
+            assert!(g.eval("foo bar counter @ counter 2 + @ here").is_ok());
+            assert_eq!(g.stack(), [6, 5, 2, 42, 11]);
+            assert_eq!(g.snapshot(), f.snapshot());
         }
 
         #[test]
-        fn can_override_other_user_defined_words() {
+        fn variables_keep_their_addresses_after_a_negative_allot() {
             let mut f = Forth::new();
-            assert!(f.eval(": foo dup ;").is_ok());
-            assert!(f.eval(": foo dup dup ;").is_ok());
-            assert!(f.eval("1 foo").is_ok());
-            assert_eq!(f.stack(), [1, 1, 1]);
+            assert!(f.eval("variable a variable b -2 allot variable c 5 c !").is_ok());
+            assert!(f.eval("3 allot -1 allot").is_ok());
+            assert!(f.eval("c @ c b here").is_ok());
+            assert_eq!(f.stack(), [5, 0, 1, 3]);
+
+            let mut g = Forth::new();
+            assert!(g.eval(&f.snapshot()).is_ok());
+            assert!(g.eval("c @ c b here").is_ok());
+            assert_eq!(g.stack(), f.stack());
+            assert_eq!(g.snapshot(), f.snapshot());
         }
 
         #[test]
-        fn can_override_built_in_words() {
+        fn strings_are_saved_as_is() {
             let mut f = Forth::new();
-            assert!(f.eval(": swap dup ;").is_ok());
-            assert!(f.eval("1 swap").is_ok());
-            assert_eq!(f.stack(), [1, 1]);
+            assert!(f.eval(": hi .\" Hello,\n  World!\" ;").is_ok());
+            let mut g = Forth::new();
+            assert!(g.eval(&f.snapshot()).is_ok());
+            assert!(g.eval("hi").is_ok());
+            assert_eq!(g.take_output(), "Hello,\n  World!");
         }
 
         #[test]
-        fn can_override_built_in_operators() {
+        fn native_words_have_to_be_registered_before_loading() {
+            let double = |stack: &mut Vec<Value>| {
+                let n = stack.pop().unwrap();
+                stack.push(n * 2);
+                Ok(())
+            };
             let mut f = Forth::new();
-            assert!(f.eval(": + * ;").is_ok());
-            assert!(f.eval("3 4 +").is_ok());
-            assert_eq!(f.stack(), [12]);
+            f.define_native("double", StackEffect::new(1, 1), double).unwrap();
+            assert!(f.eval(": quad double double ;").is_ok());
+            assert_eq!(f.snapshot(), ": quad double double ;\n");
+
+            let mut g = Forth::new();
+            assert_eq!(g.eval(&f.snapshot()).unwrap_err(), ErrorKind::UnknownWord);
+            g.define_native("double", StackEffect::new(1, 1), double).unwrap();
+            assert!(g.eval(&f.snapshot()).is_ok());
+            assert!(g.eval("3 quad").is_ok());
+            assert_eq!(g.stack(), [12]);
         }
 
         #[test]
-        fn can_use_different_words_with_the_same_name() {
+        fn include_evaluates_a_file() {
+            let file = TempFile::new("Include.fs", ": double 2 * ;\n21 double");
             let mut f = Forth::new();
-            assert!(f.eval(": foo 5 ;").is_ok());
-            assert!(f.eval(": bar foo ;").is_ok());
-            assert!(f.eval(": foo 6 ;").is_ok());
-            assert!(f.eval("bar foo").is_ok());
-            assert_eq!(f.stack(), [5, 6]);
+            assert!(f.eval(&format!("1 include {} double", file.0.display())).is_ok());
+            assert_eq!(f.stack(), [1, 84]);
         }
 
         #[test]
-        fn can_define_word_that_uses_word_with_the_same_name() {
+        fn errors_on_missing_files() {
             let mut f = Forth::new();
-            assert!(f.eval(": foo 10 ;").is_ok());
-            assert!(f.eval(": foo foo 1 + ;").is_ok());
-            assert!(f.eval("foo").is_ok());
-            assert_eq!(f.stack(), [11]);
+            let err = f.eval("1 include /no/such/File.fs").unwrap_err();
+            assert_eq!(err, ErrorKind::File);
+            assert_eq!(err.word.as_deref(), Some("/no/such/File.fs"));
+            assert_eq!(f.eval("include").unwrap_err(), ErrorKind::File);
+            assert_eq!(f.load("/no/such/file.fs").unwrap_err(), ErrorKind::File);
         }
 
         #[test]
-        fn cannot_redefine_non_negative_numbers() {
+        fn errors_on_files_including_themselves() {
+            let file = TempFile::new("loop.fs", "");
+            fs::write(&file.0, format!("include {}", file.0.display())).unwrap();
             let mut f = Forth::new();
-            assert_eq!(f.eval(": 1 2 ;"), Err(Error::InvalidWord));
+            let err = f.load(&file.0).unwrap_err();
+            assert_eq!(err, ErrorKind::RecursionLimit);
+            assert_eq!(err.word, Some(file.0.display().to_string()));
+            assert_eq!(err.position, Some(Position { line: 1, offset: 8 }));
+            assert!(f.eval("1").is_ok());
+        }
+    }
+
+    mod stack_effects {
+        use super::super::*;
+
+        fn effect(inputs: usize, outputs: usize) -> Option<StackEffect> {
+            Some(StackEffect::new(inputs, outputs))
         }
 
         #[test]
-        fn cannot_redefine_negative_numbers() {
+        fn effects_of_straight_line_definitions() {
             let mut f = Forth::new();
-            assert_eq!(f.eval(": -1 2 ;"), Err(Error::InvalidWord));
+            assert!(f.eval(": square dup * ; : nip swap drop ; : rot >r swap r> swap ;").is_ok());
+            assert_eq!(f.stack_effect("square"), effect(1, 1));
+            assert_eq!(f.stack_effect("NIP"), effect(2, 1));
+            assert_eq!(f.stack_effect("rot"), effect(3, 3));
         }
 
         #[test]
-        fn errors_if_executing_a_non_existent_word() {
+        fn effects_of_words_used_are_combined() {
             let mut f = Forth::new();
-            assert_eq!(f.eval("foo"), Err(Error::UnknownWord));
+            assert!(f.eval(": square dup * ; : sum-of-squares square swap square + ;").is_ok());
+            assert_eq!(f.stack_effect("sum-of-squares"), effect(2, 1));
         }
 
         #[test]
-        fn only_defines_locally() {
-            let mut f = Forth::new();
-            assert!(f.eval(": + - ;").is_ok());
-            assert!(f.eval("1 1 +").is_ok());
-            assert_eq!(f.stack(), [0]);
+        fn effects_of_variables_constants_and_native_words() {
             let mut f = Forth::new();
-            assert!(f.eval("1 1 +").is_ok());
-            assert_eq!(f.stack(), [2]);
+            f.define_native("pair", StackEffect::new(1, 2), |stack| {
+                stack.push(0);
+                Ok(())
+            })
+            .unwrap();
+            assert!(f.eval("variable x 5 constant five : store-pair pair x ! x ! ;").is_ok());
+            assert_eq!(f.stack_effect("x"), effect(0, 1));
+            assert_eq!(f.stack_effect("five"), effect(0, 1));
+            assert_eq!(f.stack_effect("pair"), effect(1, 2));
+            assert_eq!(f.stack_effect("store-pair"), effect(1, 0));
         }
-    }
-
-    mod case_insensitivity {
-        use super::super::*;
 
         #[test]
-        fn dup_is_case_insensitive() {
+        fn effects_of_control_flow() {
             let mut f = Forth::new();
-            assert!(f.eval("1 DUP Dup dup").is_ok());
-            assert_eq!(f.stack(), [1, 1, 1, 1]);
+            assert!(f.eval(": abs dup 0 < if -1 * then ;").is_ok());
+            assert!(f.eval(": sum 0 swap 0 do i + loop ;").is_ok());
+            assert!(f.eval(": down begin 1 - dup 0 = until ;").is_ok());
+            assert!(f.eval(": sign dup 0 < if drop -1 exit then 0 > ;").is_ok());
+            assert_eq!(f.stack_effect("abs"), effect(1, 1));
+            assert_eq!(f.stack_effect("sum"), effect(1, 1));
+            assert_eq!(f.stack_effect("down"), effect(1, 1));
+            assert_eq!(f.stack_effect("sign"), effect(1, 1));
         }
 
         #[test]
-        fn drop_is_case_insensitive() {
+        fn effects_that_depend_on_the_data_are_unknown() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 3 4 DROP Drop drop").is_ok());
-            assert_eq!(f.stack(), [1]);
+            assert!(f.eval(": maybe if 1 then ; : pushes 0 do i loop ;").is_ok());
+            assert!(f.eval(": fact dup 1 > if dup 1 - recurse * then ;").is_ok());
+            assert!(f.eval(": uses maybe 1 + ;").is_ok());
+            assert_eq!(f.stack_effect("maybe"), None);
+            assert_eq!(f.stack_effect("pushes"), None);
+            assert_eq!(f.stack_effect("fact"), None);
+            assert_eq!(f.stack_effect("uses"), None);
+            assert_eq!(f.stack_effect("unknown"), None);
         }
 
         #[test]
-        fn swap_is_case_insensitive() {
+        fn effect_follows_redefinitions() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 SWAP 3 Swap 4 swap").is_ok());
-            assert_eq!(f.stack(), [2, 3, 4, 1]);
+            assert!(f.eval(": foo 1 ; : bar foo foo ; : foo drop ;").is_ok());
+            assert_eq!(f.stack_effect("foo"), effect(1, 0));
+            assert_eq!(f.stack_effect("bar"), effect(0, 2));
         }
 
         #[test]
-        fn over_is_case_insensitive() {
+        fn rejects_return_stack_underflow() {
             let mut f = Forth::new();
-            assert!(f.eval("1 2 OVER Over over").is_ok());
-            assert_eq!(f.stack(), [1, 2, 1, 2, 1]);
+            let err = f.eval("1 : bad 1 >r r> r@ ;").unwrap_err();
+            assert_eq!(err, ErrorKind::StackUnderflow);
+            assert_eq!(err.word.as_deref(), Some("r@"));
+            assert_eq!(err.position, Some(Position { line: 1, offset: 16 }));
+            assert_eq!(f.eval("bad").unwrap_err(), ErrorKind::UnknownWord);
+            assert_eq!(f.eval(": worse if r> then ;").unwrap_err(), ErrorKind::StackUnderflow);
         }
 
         #[test]
-        fn user_defined_words_are_case_insensitive() {
+        fn return_stack_use_depending_on_the_data_is_allowed() {
             let mut f = Forth::new();
-            assert!(f.eval(": foo dup ;").is_ok());
-            assert!(f.eval("1 FOO Foo foo").is_ok());
-            assert_eq!(f.stack(), [1, 1, 1, 1]);
+            assert!(f.eval(": odd dup if >r then r> ;").is_ok());
+            assert!(f.eval("5 1 odd").is_ok());
+            assert_eq!(f.stack(), [5, 1]);
+            assert_eq!(f.eval("0 odd").unwrap_err(), ErrorKind::StackUnderflow);
         }
 
         #[test]
-        fn definitions_are_case_insensitive() {
+        fn only_pure_definitions_are_compiled_to_nothing() {
             let mut f = Forth::new();
-            assert!(f.eval(": SWAP DUP Dup dup ;").is_ok());
-            assert!(f.eval("1 swap").is_ok());
-            assert_eq!(f.stack(), [1, 1, 1, 1]);
+            assert!(f.eval(": noop 1 2 swap drop drop ; : overflow 2147483647 1 + drop ;").is_ok());
+            assert!(f.definitions[f.words["noop"]].code.is_empty());
+            assert_eq!(f.stack_effect("noop"), effect(0, 0));
+            assert_eq!(f.eval("overflow").unwrap_err(), ErrorKind::Overflow);
+        }
+
+        #[test]
+        fn effects_are_displayed_in_forth_notation() {
+            assert_eq!(StackEffect::new(2, 1).to_string(), "( 2 -- 1 )");
         }
     }
-}
+}
\ No newline at end of file
@@ -1,18 +1,39 @@
-use std::collections::{HashMap, VecDeque};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
-type ComputeFn<T> = Box<dyn Fn(&[T]) -> T>;
+use crate::medium::dot_dsl::graph::Graph;
+use crate::medium::dot_dsl::graph::graph_items::edge::Edge;
+use crate::medium::dot_dsl::graph::graph_items::node::Node;
+
+type ComputeFn<T> = Box<dyn Fn(&[T]) -> Result<T, String>>;
 type Callback<'a, T> = Box<dyn FnMut(T) + 'a>;
 type Callbacks<'a, T> = Vec<(CallbackId, Callback<'a, T>)>;
+type Job<T> = Box<dyn FnOnce(&mut Reactor<'static, T>) + Send>;
+/// (input, old value, new value) of every input changed by one `set_values` call
+type HistoryEntry<T> = Vec<(InputCellId, T, T)>;
+
+static REACTOR_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-static GLOBAL_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Default number of input changes that can be undone
+const HISTORY_LIMIT: usize = 100;
+
+/// Identifies an item by the reactor that created it and its creation order there
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Id {
+    reactor: u64,
+    index: u64,
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct InputCellId(u64);
+pub struct InputCellId(Id);
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct ComputeCellId(u64);
+pub struct ComputeCellId(Id);
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct CallbackId(u64);
+pub struct CallbackId(Id);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum CellId {
@@ -20,132 +41,430 @@ pub enum CellId {
     Compute(ComputeCellId),
 }
 
+impl CellId {
+    fn id(self) -> Id {
+        match self {
+            CellId::Input(InputCellId(id)) | CellId::Compute(ComputeCellId(id)) => id,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum RemoveCallbackError {
     NonexistentCell,
     NonexistentCallback,
+    /// the cell id was created by another reactor
+    ForeignCell,
+    /// the callback id was created by another reactor
+    ForeignCallback,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RemoveCellError {
+    NonexistentCell,
+    /// the id was created by another reactor
+    ForeignCell,
+    /// compute cells that still depend on the cell
+    HasDependents(Vec<ComputeCellId>),
+}
+
+/// Failure of a compute function, shared by every cell depending on the failed one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComputeError {
+    /// the cell whose function failed
+    pub cell: ComputeCellId,
+    pub message: String,
+}
+
+impl fmt::Display for ComputeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "compute cell {} failed: {}", self.cell.0.index, self.message)
+    }
+}
+
+impl std::error::Error for ComputeError {}
+
+struct ComputeCell<T> {
+    /// last computed value, outdated while `dirty`
+    value: RefCell<Result<T, ComputeError>>,
+    dirty: Cell<bool>,
+    deps: Vec<CellId>,
+    compute_func: ComputeFn<T>,
 }
 
 pub struct Reactor<'a, T> {
+    id: u64,
+    /// index of the next created item
+    next_index: u64,
+    /// recompute cells only when their values are needed
+    lazy: bool,
     inputs: HashMap<InputCellId, T>,
-    computes: HashMap<ComputeCellId, (T, Vec<CellId>, ComputeFn<T>)>,
+    computes: HashMap<ComputeCellId, ComputeCell<T>>,
     dependents: HashMap<CellId, Vec<ComputeCellId>>,
     callbacks: HashMap<ComputeCellId, Callbacks<'a, T>>,
+    undo_history: VecDeque<HistoryEntry<T>>,
+    redo_history: Vec<HistoryEntry<T>>,
+    history_limit: usize,
+}
+
+impl<'a, T: Clone + PartialEq> Default for Reactor<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
+impl<'a, T: Clone + PartialEq> Reactor<'a, T> {
     pub fn new() -> Self {
         Self {
+            id: REACTOR_COUNTER.fetch_add(1, Ordering::Relaxed),
+            next_index: 0,
+            lazy: false,
             inputs: HashMap::new(),
             computes: HashMap::new(),
             dependents: HashMap::new(),
             callbacks: HashMap::new(),
+            undo_history: VecDeque::new(),
+            redo_history: Vec::new(),
+            history_limit: HISTORY_LIMIT,
         }
     }
 
+    /// Reactor whose compute cells are only marked as outdated when their inputs change,
+    /// and recomputed when their value is asked for or their callbacks need it
+    pub fn new_lazy() -> Self {
+        Self { lazy: true, ..Self::new() }
+    }
+
     pub fn create_input(&mut self, initial: T) -> InputCellId {
-        let input_id = InputCellId(GLOBAL_ID_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let input_id = InputCellId(self.next_id());
         self.inputs.insert(input_id, initial);
         input_id
     }
 
+    /// Fails with the first dependency that doesn't exist. Cells of other reactors
+    /// don't exist here either, `owns` tells the two cases apart.
     pub fn create_compute<F: Fn(&[T]) -> T + 'static>(
         &mut self,
         dependencies: &[CellId],
         compute_func: F,
     ) -> Result<ComputeCellId, CellId> {
-        for &cell_id in dependencies {
-            match cell_id {
-                CellId::Input(id) if !self.inputs.contains_key(&id) => return Err(cell_id),
-                CellId::Compute(id) if !self.computes.contains_key(&id) => return Err(cell_id),
-                _ => continue,
-            }
+        self.create_fallible_compute(dependencies, move |values| {
+            Ok::<T, String>(compute_func(values))
+        })
+    }
+
+    /// Creates a compute cell whose function may fail. A failed cell holds the error
+    /// instead of a value, and so do all cells depending on it, until it succeeds again.
+    pub fn create_fallible_compute<E, F>(
+        &mut self,
+        dependencies: &[CellId],
+        compute_func: F,
+    ) -> Result<ComputeCellId, CellId>
+    where
+        E: ToString,
+        F: Fn(&[T]) -> Result<T, E> + 'static,
+    {
+        if let Some(&cell_id) = dependencies.iter().find(|&&cell_id| !self.contains(cell_id)) {
+            return Err(cell_id);
         }
-        let deps = dependencies.to_vec();
-        let values: Vec<T> = dependencies
-            .iter()
-            .map(|&id| self.value(id).unwrap())
-            .collect();
-        let initial_value = compute_func(&values);
+        let compute_id = ComputeCellId(self.next_id());
+        let compute_func: ComputeFn<T> =
+            Box::new(move |values| compute_func(values).map_err(|e| e.to_string()));
+        let value = self.compute(compute_id, dependencies, &compute_func);
 
-        let compute_id = ComputeCellId(GLOBAL_ID_COUNTER.fetch_add(1, Ordering::Relaxed));
-        self.computes
-            .insert(compute_id, (initial_value, deps, Box::new(compute_func)));
+        let cell = ComputeCell {
+            value: RefCell::new(value),
+            dirty: Cell::new(false),
+            deps: dependencies.to_vec(),
+            compute_func,
+        };
+        self.computes.insert(compute_id, cell);
         for &dep_id in dependencies {
             self.dependents.entry(dep_id).or_default().push(compute_id);
         }
         Ok(compute_id)
     }
 
+    /// Value of a cell, `None` if there is no such cell or it holds an error.
+    /// Cells of other reactors don't exist here.
     pub fn value(&self, id: CellId) -> Option<T> {
         match id {
-            CellId::Input(input_id) => self.inputs.get(&input_id).copied(),
-            CellId::Compute(compute_id) => self.computes.get(&compute_id).map(|v| v.0),
+            CellId::Input(input_id) => self.inputs.get(&input_id).cloned(),
+            CellId::Compute(compute_id) => self.current(compute_id)?.ok(),
         }
     }
 
+    /// Error held by a compute cell
+    pub fn error(&self, id: ComputeCellId) -> Option<ComputeError> {
+        self.current(id)?.err()
+    }
+
+    /// Whether the id was created by this reactor. Methods that can't report why a cell
+    /// is missing treat ids of other reactors as ids of nonexistent cells.
+    pub fn owns(&self, id: CellId) -> bool {
+        id.id().reactor == self.id
+    }
+
+    /// Returns `false` if the input doesn't exist, which is the case for inputs of other reactors
     pub fn set_value(&mut self, id: InputCellId, new_value: T) -> bool {
-        if !self.inputs.contains_key(&id) {
+        self.set_values([(id, new_value)])
+    }
+
+    /// Sets several inputs at once. Compute cells are updated only after all of them
+    /// are set, so callbacks never see an intermediate state and fire at most once.
+    /// Nothing is changed if any of the inputs doesn't exist or belongs to another reactor.
+    pub fn set_values<I: IntoIterator<Item = (InputCellId, T)>>(&mut self, values: I) -> bool {
+        let values: Vec<_> = values.into_iter().collect();
+        if values.iter().any(|(id, _)| !self.inputs.contains_key(id)) {
+            return false;
+        }
+
+        let entry = self.apply(values);
+        if !entry.is_empty() {
+            self.redo_history.clear();
+            self.undo_history.push_back(entry);
+            self.trim_history();
+        }
+        true
+    }
+
+    /// Reverts the last `set_value` or `set_values` call that changed anything.
+    /// Returns `false` if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let Some(entry) = self.undo_history.pop_back() else {
+            return false;
+        };
+        self.apply(entry.iter().map(|(id, old_value, _)| (*id, old_value.clone())).collect());
+        self.redo_history.push(entry);
+        true
+    }
+
+    /// Repeats the last undone change, unless inputs have been set since.
+    /// Returns `false` if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let Some(entry) = self.redo_history.pop() else {
             return false;
+        };
+        self.apply(entry.iter().map(|(id, _, new_value)| (*id, new_value.clone())).collect());
+        self.undo_history.push_back(entry);
+        true
+    }
+
+    /// Sets how many changes can be undone, dropping the oldest ones over the limit
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.trim_history();
+    }
+
+    /// Sets existing inputs and propagates the changes, returns what has changed
+    fn apply(&mut self, values: Vec<(InputCellId, T)>) -> HistoryEntry<T> {
+        let mut entry: HistoryEntry<T> = Vec::new();
+        for (id, new_value) in values {
+            let old_value = self.inputs.insert(id, new_value.clone()).unwrap();
+            match entry.iter_mut().find(|(changed_id, _, _)| *changed_id == id) {
+                Some((_, _, value)) => *value = new_value,
+                None => entry.push((id, old_value, new_value)),
+            }
+        }
+        entry.retain(|(_, old_value, new_value)| old_value != new_value);
+
+        self.propagate(entry.iter().map(|(id, _, _)| CellId::Input(*id)).collect());
+        entry
+    }
+
+    fn trim_history(&mut self) {
+        while self.undo_history.len() > self.history_limit {
+            self.undo_history.pop_front();
+        }
+    }
+
+    fn next_id(&mut self) -> Id {
+        self.next_index += 1;
+        Id { reactor: self.id, index: self.next_index - 1 }
+    }
+
+    /// Value of a compute cell, recomputed first if it is outdated
+    fn current(&self, id: ComputeCellId) -> Option<Result<T, ComputeError>> {
+        let cell = self.computes.get(&id)?;
+        if cell.dirty.get() {
+            let value = self.compute(id, &cell.deps, &cell.compute_func);
+            *cell.value.borrow_mut() = value;
+            cell.dirty.set(false);
         }
-        self.inputs.insert(id, new_value);
+        Some(cell.value.borrow().clone())
+    }
 
-        let mut queue = VecDeque::new();
-        queue.push_back(CellId::Input(id));
+    /// Runs `compute_func` of the cell `id`, unless one of its dependencies holds an error
+    fn compute(
+        &self,
+        id: ComputeCellId,
+        deps: &[CellId],
+        compute_func: &ComputeFn<T>,
+    ) -> Result<T, ComputeError> {
+        let dep_values = deps
+            .iter()
+            .map(|&dep_id| match dep_id {
+                CellId::Input(input_id) => Ok(self.inputs[&input_id].clone()),
+                CellId::Compute(compute_id) => self.current(compute_id).unwrap(),
+            })
+            .collect::<Result<Vec<T>, _>>()?;
+        compute_func(&dep_values).map_err(|message| ComputeError { cell: id, message })
+    }
 
-        let mut callbacks_to_trigger: HashMap<(ComputeCellId, CallbackId), T> = HashMap::new();
+    /// Recomputes every compute cell depending on the `changed` cells once,
+    /// then fires the callbacks of the cells whose value has changed.
+    /// Lazy reactors only recompute the cells with callbacks and what they depend on.
+    fn propagate(&mut self, mut changed: HashSet<CellId>) {
+        let mut affected = Vec::new();
+        // cells reachable by several paths are only queued once
+        let mut queued: HashSet<ComputeCellId> = HashSet::new();
+        let mut queue: VecDeque<CellId> = changed.iter().copied().collect();
         while let Some(cell_id) = queue.pop_front() {
-            if let Some(dependents) = self.dependents.get(&cell_id) {
-                for &compute_id in dependents {
-                    let (old_value, deps, compute_func) = &self.computes[&compute_id];
-                    let dep_values: Vec<T> = deps.iter()
-                        .map(|&dep_id| self.value(dep_id).unwrap())
-                        .collect();
-
-                    let new_compute_value = compute_func(&dep_values);
-                    if new_compute_value != *old_value {
-                        self.computes.get_mut(&compute_id).unwrap().0 = new_compute_value;
-                        queue.push_back(CellId::Compute(compute_id));
-
-                        let callbacks: Vec<_> = {
-                            let mut callbacks = Vec::new();
-                            if let Some(cb_list) = self.callbacks.get(&compute_id) {
-                                callbacks.extend(cb_list.iter().map(|(id, _)| *id));
-                            }
-                            callbacks
-                        };
-
-                        for cb_id in callbacks {
-                            callbacks_to_trigger.insert((compute_id, cb_id), new_compute_value);
+            for &compute_id in self.dependents.get(&cell_id).into_iter().flatten() {
+                if queued.insert(compute_id) {
+                    affected.push(compute_id);
+                    queue.push_back(CellId::Compute(compute_id));
+                }
+            }
+        }
+        // cells can only depend on cells created before them, so creation order
+        // is a topological order
+        affected.sort_by_key(|id| id.0.index);
+        affected.dedup();
+
+        let mut updated = Vec::new();
+        for compute_id in affected {
+            let cell = &self.computes[&compute_id];
+            if self.lazy {
+                cell.dirty.set(true);
+                if self.callbacks.get(&compute_id).is_none_or(|callbacks| callbacks.is_empty()) {
+                    continue;
+                }
+            } else if !cell.deps.iter().any(|dep_id| changed.contains(dep_id)) {
+                continue;
+            }
+
+            let old_value = cell.value.borrow().clone();
+            cell.dirty.set(true);
+            let new_value = self.current(compute_id).unwrap();
+            if new_value != old_value {
+                changed.insert(CellId::Compute(compute_id));
+                if let Ok(value) = new_value {
+                    updated.push((compute_id, value));
+                }
+            }
+        }
+
+        // callbacks only get values, failures can be checked with `error`
+        for (compute_id, value) in updated {
+            for (_, callback) in self.callbacks.get_mut(&compute_id).into_iter().flatten() {
+                callback(value.clone());
+            }
+        }
+    }
+
+    /// Removes a cell and its callbacks. Cells other cells depend on are not removed,
+    /// see `remove_cell_cascade`.
+    pub fn remove_cell(&mut self, id: CellId) -> Result<(), RemoveCellError> {
+        self.check_removal(id)?;
+        if let Some(dependents) = self.dependents.get(&id) {
+            let mut dependents = dependents.clone();
+            dependents.sort_by_key(|id| id.0.index);
+            dependents.dedup();
+            return Err(RemoveCellError::HasDependents(dependents));
+        }
+
+        match id {
+            CellId::Input(input_id) => {
+                self.inputs.remove(&input_id);
+                // changes of the input can't be undone anymore
+                let entries = self.undo_history.iter_mut().chain(self.redo_history.iter_mut());
+                for entry in entries {
+                    entry.retain(|(id, _, _)| *id != input_id);
+                }
+                self.undo_history.retain(|entry| !entry.is_empty());
+                self.redo_history.retain(|entry| !entry.is_empty());
+            }
+            CellId::Compute(compute_id) => {
+                let cell = self.computes.remove(&compute_id).unwrap();
+                self.callbacks.remove(&compute_id);
+                for dep_id in cell.deps {
+                    if let Some(dependents) = self.dependents.get_mut(&dep_id) {
+                        dependents.retain(|&dependent| dependent != compute_id);
+                        if dependents.is_empty() {
+                            self.dependents.remove(&dep_id);
                         }
                     }
                 }
             }
         }
+        Ok(())
+    }
 
-        for ((compute_id, cb_id), val) in callbacks_to_trigger.iter() {
-            if let Some(cb) = self.callbacks.get_mut(compute_id)
-                .and_then(|cbs| cbs.iter_mut().find(|(id, _)| *id == *cb_id))
-            {
-                (cb.1)(*val);
+    /// Removes a cell together with every compute cell depending on it, directly or not.
+    /// Returns the removed dependents.
+    pub fn remove_cell_cascade(
+        &mut self,
+        id: CellId,
+    ) -> Result<Vec<ComputeCellId>, RemoveCellError> {
+        self.check_removal(id)?;
+        let mut removed = Vec::new();
+        let mut queued: HashSet<ComputeCellId> = HashSet::new();
+        let mut queue = VecDeque::from([id]);
+        while let Some(cell_id) = queue.pop_front() {
+            for &compute_id in self.dependents.get(&cell_id).into_iter().flatten() {
+                if queued.insert(compute_id) {
+                    removed.push(compute_id);
+                    queue.push_back(CellId::Compute(compute_id));
+                }
             }
-        }        
+        }
+        removed.sort_by_key(|id| id.0.index);
+        removed.dedup();
 
-        true
+        // the most recent cells first, so nothing removed has dependents left
+        for &compute_id in removed.iter().rev() {
+            self.remove_cell(CellId::Compute(compute_id)).unwrap();
+        }
+        self.remove_cell(id).unwrap();
+        Ok(removed)
     }
 
-    pub fn add_callback<F: FnMut(T) + 'a>(&mut self, id: ComputeCellId, callback: F) -> Option<CallbackId> {        
-        if !self.computes.contains_key(&id) {
-            return None;
+    fn check_removal(&self, id: CellId) -> Result<(), RemoveCellError> {
+        if !self.owns(id) {
+            return Err(RemoveCellError::ForeignCell);
+        }
+        if !self.contains(id) {
+            return Err(RemoveCellError::NonexistentCell);
+        }
+        Ok(())
+    }
+
+    fn contains(&self, id: CellId) -> bool {
+        match id {
+            CellId::Input(input_id) => self.inputs.contains_key(&input_id),
+            CellId::Compute(compute_id) => self.computes.contains_key(&compute_id),
         }
+    }
 
-        let callback_id = CallbackId(GLOBAL_ID_COUNTER.fetch_add(1, Ordering::Relaxed));        
+    /// Returns `None` if the cell doesn't exist, which is the case for cells of other reactors
+    pub fn add_callback<F: FnMut(T) + 'a>(&mut self, id: ComputeCellId, callback: F) -> Option<CallbackId> {        
+        // callbacks fire on changes from the value they start with, so it must be up to date
+        self.current(id).as_ref()?;
+
+        let callback_id = CallbackId(self.next_id());
         let holder = (callback_id, Box::new(callback) as Callback<'a, T>);
         self.callbacks.entry(id).or_default().push(holder);
         Some(callback_id)
     }
 
     pub fn remove_callback(&mut self, compute_id: ComputeCellId, callback_id: CallbackId) -> Result<(), RemoveCallbackError> {
+        if !self.owns(CellId::Compute(compute_id)) {
+            return Err(RemoveCallbackError::ForeignCell);
+        }
+        if callback_id.0.reactor != self.id {
+            return Err(RemoveCallbackError::ForeignCallback);
+        }
         if !self.computes.contains_key(&compute_id) {
             return Err(RemoveCallbackError::NonexistentCell);
         }
@@ -159,6 +478,186 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
     }
 }
 
+impl<T: Clone + PartialEq + fmt::Debug> Reactor<'_, T> {
+    /// Dependency graph with a node per cell, holding its value or error, and edges
+    /// from every dependency to the cells using it, labeled with the argument index
+    pub fn to_graph(&self) -> Graph {
+        let mut inputs: Vec<_> = self.inputs.iter().collect();
+        inputs.sort_by_key(|(id, _)| id.0.index);
+        let mut computes: Vec<_> = self.computes.iter().collect();
+        computes.sort_by_key(|(id, _)| id.0.index);
+
+        let mut nodes = Vec::new();
+        for (id, value) in inputs {
+            let value = format!("{value:?}");
+            let label = format!("input {}\n{value}", id.0.index);
+            let attrs = [("shape", "box"), ("label", &*label), ("value", &value)];
+            nodes.push(Node::new(&Self::node_name(CellId::Input(*id))).with_attrs(&attrs));
+        }
+        let mut edges = Vec::new();
+        for (id, cell) in computes {
+            let name = Self::node_name(CellId::Compute(*id));
+            let node = match self.current(*id).unwrap() {
+                Ok(value) => {
+                    let value = format!("{value:?}");
+                    let label = format!("compute {}\n{value}", id.0.index);
+                    Node::new(&name).with_attrs(&[("label", &label), ("value", &value)])
+                }
+                Err(error) => {
+                    let label = format!("compute {}\nerror: {}", id.0.index, error.message);
+                    let attrs = [("label", &*label), ("error", &error.message), ("color", "red")];
+                    Node::new(&name).with_attrs(&attrs)
+                }
+            };
+            nodes.push(node);
+            for (index, &dep_id) in cell.deps.iter().enumerate() {
+                let edge = Edge::new(&Self::node_name(dep_id), &name);
+                edges.push(edge.with_attrs(&[("label", &index.to_string())]));
+            }
+        }
+        Graph::new().with_nodes(&nodes).with_edges(&edges)
+    }
+
+    /// The dependency graph as Graphviz text, see `to_graph`
+    pub fn to_dot(&self) -> String {
+        self.to_graph().to_string()
+    }
+
+    fn node_name(id: CellId) -> String {
+        match id {
+            CellId::Input(InputCellId(id)) => format!("input{}", id.index),
+            CellId::Compute(ComputeCellId(id)) => format!("compute{}", id.index),
+        }
+    }
+}
+
+/// Handle to a `Reactor` running on its own thread, which can be cloned and used
+/// from any thread. Calls are executed one at a time in the order they arrive,
+/// so propagation is serialized. Functions and callbacks run on the reactor thread
+/// and must not call the reactor themselves.
+#[derive(Clone)]
+pub struct SharedReactor<T> {
+    jobs: mpsc::Sender<Job<T>>,
+}
+
+impl<T: Clone + PartialEq + Send + 'static> Default for SharedReactor<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + PartialEq + Send + 'static> SharedReactor<T> {
+    /// Starts the reactor thread, it stops when the last handle is dropped
+    pub fn new() -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job<T>>();
+        thread::spawn(move || {
+            let mut reactor = Reactor::new();
+            for job in receiver {
+                job(&mut reactor);
+            }
+        });
+        Self { jobs }
+    }
+
+    pub fn create_input(&self, initial: T) -> InputCellId {
+        self.run(move |reactor| reactor.create_input(initial))
+    }
+
+    pub fn create_compute<F: Fn(&[T]) -> T + Send + 'static>(
+        &self,
+        dependencies: &[CellId],
+        compute_func: F,
+    ) -> Result<ComputeCellId, CellId> {
+        let dependencies = dependencies.to_vec();
+        self.run(move |reactor| reactor.create_compute(&dependencies, compute_func))
+    }
+
+    pub fn create_fallible_compute<E, F>(
+        &self,
+        dependencies: &[CellId],
+        compute_func: F,
+    ) -> Result<ComputeCellId, CellId>
+    where
+        E: ToString,
+        F: Fn(&[T]) -> Result<T, E> + Send + 'static,
+    {
+        let dependencies = dependencies.to_vec();
+        self.run(move |reactor| reactor.create_fallible_compute(&dependencies, compute_func))
+    }
+
+    pub fn value(&self, id: CellId) -> Option<T> {
+        self.run(move |reactor| reactor.value(id))
+    }
+
+    pub fn owns(&self, id: CellId) -> bool {
+        self.run(move |reactor| reactor.owns(id))
+    }
+
+    pub fn error(&self, id: ComputeCellId) -> Option<ComputeError> {
+        self.run(move |reactor| reactor.error(id))
+    }
+
+    pub fn set_value(&self, id: InputCellId, new_value: T) -> bool {
+        self.run(move |reactor| reactor.set_value(id, new_value))
+    }
+
+    pub fn set_values<I: IntoIterator<Item = (InputCellId, T)>>(&self, values: I) -> bool {
+        let values: Vec<_> = values.into_iter().collect();
+        self.run(move |reactor| reactor.set_values(values))
+    }
+
+    pub fn undo(&self) -> bool {
+        self.run(|reactor| reactor.undo())
+    }
+
+    pub fn redo(&self) -> bool {
+        self.run(|reactor| reactor.redo())
+    }
+
+    pub fn set_history_limit(&self, limit: usize) {
+        self.run(move |reactor| reactor.set_history_limit(limit))
+    }
+
+    pub fn remove_cell(&self, id: CellId) -> Result<(), RemoveCellError> {
+        self.run(move |reactor| reactor.remove_cell(id))
+    }
+
+    pub fn remove_cell_cascade(&self, id: CellId) -> Result<Vec<ComputeCellId>, RemoveCellError> {
+        self.run(move |reactor| reactor.remove_cell_cascade(id))
+    }
+
+    pub fn add_callback<F: FnMut(T) + Send + 'static>(
+        &self,
+        id: ComputeCellId,
+        callback: F,
+    ) -> Option<CallbackId> {
+        self.run(move |reactor| reactor.add_callback(id, callback))
+    }
+
+    pub fn remove_callback(
+        &self,
+        compute_id: ComputeCellId,
+        callback_id: CallbackId,
+    ) -> Result<(), RemoveCallbackError> {
+        self.run(move |reactor| reactor.remove_callback(compute_id, callback_id))
+    }
+
+    /// Runs `job` on the reactor thread and waits for its result
+    fn run<R, F>(&self, job: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Reactor<'static, T>) -> R + Send + 'static,
+    {
+        let (reply, result) = mpsc::channel();
+        self.jobs
+            .send(Box::new(move |reactor| {
+                let _ = reply.send(job(reactor));
+            }))
+            .expect("reactor thread has stopped");
+        result.recv().expect("reactor thread has panicked")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -365,6 +864,11 @@ mod tests {
         let callback = reactor.add_callback(output, |_| ()).unwrap();
         assert_eq!(
             reactor.remove_callback(dummy_output, callback),
+            Err(RemoveCallbackError::ForeignCell)
+        );
+        assert_eq!(reactor.remove_cell(CellId::Compute(output)), Ok(()));
+        assert_eq!(
+            reactor.remove_callback(output, callback),
             Err(RemoveCallbackError::NonexistentCell)
         );
     }
@@ -609,4 +1113,716 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn batch_updates_do_not_fire_callbacks_on_intermediate_states() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let width = reactor.create_input(2);
+        let height = reactor.create_input(3);
+        let is_square = reactor
+            .create_compute(&[CellId::Input(width), CellId::Input(height)], |v| {
+                (v[0] == v[1]) as i32
+            })
+            .unwrap();
+        assert!(
+            reactor
+                .add_callback(is_square, |v| cb.callback_called(v))
+                .is_some()
+        );
+        assert!(reactor.set_values([(width, 3), (height, 2)]));
+        cb.expect_not_to_have_been_called();
+        assert!(reactor.set_values([(width, 5), (height, 5)]));
+        cb.expect_to_have_been_called_with(1);
+    }
+
+    #[test]
+    fn batch_updates_fire_callbacks_once_with_the_final_value() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let a = reactor.create_input(1);
+        let b = reactor.create_input(2);
+        let sum = reactor
+            .create_compute(&[CellId::Input(a), CellId::Input(b)], |v| v[0] + v[1])
+            .unwrap();
+        let doubled = reactor
+            .create_compute(&[CellId::Compute(sum), CellId::Input(a)], |v| v[0] * 2 + v[1])
+            .unwrap();
+        assert!(
+            reactor
+                .add_callback(doubled, |v| cb.callback_called(v))
+                .is_some()
+        );
+        assert!(reactor.set_values([(a, 10), (b, 20), (a, 5)]));
+        assert_eq!(reactor.value(CellId::Compute(sum)), Some(25));
+        cb.expect_to_have_been_called_with(55);
+    }
+
+    #[test]
+    fn batch_updates_restoring_the_old_values_fire_nothing() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let output = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        assert!(
+            reactor
+                .add_callback(output, |v| cb.callback_called(v))
+                .is_some()
+        );
+        assert!(reactor.set_values([(input, 7), (input, 1)]));
+        cb.expect_not_to_have_been_called();
+    }
+
+    #[test]
+    fn batch_updates_with_a_nonexistent_input_change_nothing() {
+        let mut dummy_reactor = Reactor::new();
+        let dummy_input = dummy_reactor.create_input(1);
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        assert!(!reactor.set_values([(input, 2), (dummy_input, 3)]));
+        assert_eq!(reactor.value(CellId::Input(input)), Some(1));
+    }
+
+    /// Chain of `levels` diamonds `top -> (left, right) -> bottom` below `input`,
+    /// so the last cell is reachable by 2^levels paths. Returns the cells created
+    fn diamond_chain(
+        reactor: &mut Reactor<i64>,
+        input: InputCellId,
+        levels: usize,
+    ) -> Vec<ComputeCellId> {
+        let mut cells = Vec::new();
+        let mut top = CellId::Input(input);
+        for _ in 0..levels {
+            let left = reactor.create_compute(&[top], |v| v[0] + 1).unwrap();
+            let right = reactor.create_compute(&[top], |v| v[0] + 2).unwrap();
+            let deps = [CellId::Compute(left), CellId::Compute(right)];
+            let bottom = reactor.create_compute(&deps, |v| v[0].max(v[1])).unwrap();
+            cells.extend([left, right, bottom]);
+            top = CellId::Compute(bottom);
+        }
+        cells
+    }
+
+    #[test]
+    fn updates_visit_cells_reachable_by_many_paths_once() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(0);
+        let bottom = *diamond_chain(&mut reactor, input, 64).last().unwrap();
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        assert!(reactor.add_callback(bottom, move |_| counter.set(counter.get() + 1)).is_some());
+        assert!(reactor.set_value(input, 1));
+        assert_eq!(reactor.value(CellId::Compute(bottom)), Some(129));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn cells_without_dependents_can_be_removed() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let output = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        assert_eq!(reactor.remove_cell(CellId::Compute(output)), Ok(()));
+        assert_eq!(reactor.value(CellId::Compute(output)), None);
+        assert_eq!(reactor.add_callback(output, |_| ()), None);
+        assert_eq!(reactor.remove_cell(CellId::Input(input)), Ok(()));
+        assert_eq!(reactor.value(CellId::Input(input)), None);
+        assert!(!reactor.set_value(input, 2));
+        assert!(reactor.dependents.is_empty());
+    }
+
+    #[test]
+    fn error_removing_a_nonexistent_cell() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        assert!(reactor.remove_cell(CellId::Input(input)).is_ok());
+        assert_eq!(
+            reactor.remove_cell(CellId::Input(input)),
+            Err(RemoveCellError::NonexistentCell)
+        );
+        assert_eq!(
+            reactor.remove_cell_cascade(CellId::Input(input)),
+            Err(RemoveCellError::NonexistentCell)
+        );
+    }
+
+    #[test]
+    fn error_removing_a_cell_with_dependents() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let plus_one = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        let doubled = reactor
+            .create_compute(&[CellId::Input(input), CellId::Input(input)], |v| v[0] + v[1])
+            .unwrap();
+        assert_eq!(
+            reactor.remove_cell(CellId::Input(input)),
+            Err(RemoveCellError::HasDependents(vec![plus_one, doubled]))
+        );
+        assert!(reactor.set_value(input, 2));
+        assert_eq!(reactor.value(CellId::Compute(doubled)), Some(4));
+    }
+
+    #[test]
+    fn removed_cells_no_longer_fire_callbacks() {
+        let cb1 = CallbackRecorder::new();
+        let cb2 = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let plus_one = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        let minus_one = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] - 1)
+            .unwrap();
+        assert!(reactor.add_callback(plus_one, |v| cb1.callback_called(v)).is_some());
+        assert!(reactor.add_callback(minus_one, |v| cb2.callback_called(v)).is_some());
+        assert!(reactor.remove_cell(CellId::Compute(plus_one)).is_ok());
+        assert!(reactor.set_value(input, 5));
+        cb1.expect_not_to_have_been_called();
+        cb2.expect_to_have_been_called_with(4);
+        assert!(!reactor.callbacks.contains_key(&plus_one));
+    }
+
+    #[test]
+    fn cascade_removal_removes_all_dependents() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let other = reactor.create_input(2);
+        let plus_one = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        let sum = reactor
+            .create_compute(&[CellId::Compute(plus_one), CellId::Input(other)], |v| v[0] + v[1])
+            .unwrap();
+        let doubled = reactor
+            .create_compute(&[CellId::Input(other)], |v| v[0] * 2)
+            .unwrap();
+        assert_eq!(
+            reactor.remove_cell_cascade(CellId::Compute(plus_one)),
+            Ok(vec![sum])
+        );
+        assert_eq!(reactor.value(CellId::Compute(plus_one)), None);
+        assert_eq!(reactor.value(CellId::Compute(sum)), None);
+        assert_eq!(reactor.dependents[&CellId::Input(other)], [doubled]);
+        assert!(!reactor.dependents.contains_key(&CellId::Input(input)));
+        assert_eq!(reactor.remove_cell_cascade(CellId::Input(input)), Ok(vec![]));
+        assert!(reactor.set_value(other, 5));
+        assert_eq!(reactor.value(CellId::Compute(doubled)), Some(10));
+    }
+
+    #[test]
+    fn cascade_removal_visits_cells_reachable_by_many_paths_once() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(0);
+        let cells = diamond_chain(&mut reactor, input, 64);
+        assert_eq!(reactor.remove_cell_cascade(CellId::Input(input)), Ok(cells));
+        assert!(reactor.computes.is_empty());
+        assert!(reactor.dependents.is_empty());
+    }
+
+    #[test]
+    fn cells_can_hold_non_copy_values() {
+        let mut reactor = Reactor::new();
+        let first = reactor.create_input(String::from("Ada"));
+        let last = reactor.create_input(String::from("Lovelace"));
+        let full = reactor
+            .create_compute(&[CellId::Input(first), CellId::Input(last)], |v| {
+                format!("{} {}", v[0], v[1])
+            })
+            .unwrap();
+        let names: std::rc::Rc<std::cell::RefCell<Vec<String>>> = Default::default();
+        let recorded = names.clone();
+        assert!(
+            reactor
+                .add_callback(full, move |v| recorded.borrow_mut().push(v))
+                .is_some()
+        );
+        assert!(reactor.set_value(first, String::from("Augusta Ada")));
+        assert_eq!(
+            reactor.value(CellId::Compute(full)),
+            Some(String::from("Augusta Ada Lovelace"))
+        );
+        assert_eq!(*names.borrow(), ["Augusta Ada Lovelace"]);
+    }
+
+    #[test]
+    fn compute_cells_over_vectors() {
+        let mut reactor = Reactor::new();
+        let items = reactor.create_input(vec![3, 1, 2]);
+        let sorted = reactor
+            .create_compute(&[CellId::Input(items)], |v| {
+                let mut sorted = v[0].clone();
+                sorted.sort();
+                sorted
+            })
+            .unwrap();
+        assert_eq!(reactor.value(CellId::Compute(sorted)), Some(vec![1, 2, 3]));
+        assert!(reactor.set_value(items, vec![9, 7]));
+        assert_eq!(reactor.value(CellId::Compute(sorted)), Some(vec![7, 9]));
+    }
+
+    #[test]
+    fn fallible_compute_cells_hold_errors() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(4);
+        let half = reactor
+            .create_fallible_compute(&[CellId::Input(input)], |v| {
+                if v[0] % 2 == 0 { Ok(v[0] / 2) } else { Err(format!("{} is odd", v[0])) }
+            })
+            .unwrap();
+        assert_eq!(reactor.value(CellId::Compute(half)), Some(2));
+        assert_eq!(reactor.error(half), None);
+        assert!(reactor.set_value(input, 3));
+        assert_eq!(reactor.value(CellId::Compute(half)), None);
+        assert_eq!(
+            reactor.error(half),
+            Some(ComputeError { cell: half, message: String::from("3 is odd") })
+        );
+        assert!(reactor.set_value(input, 10));
+        assert_eq!(reactor.value(CellId::Compute(half)), Some(5));
+        assert_eq!(reactor.error(half), None);
+    }
+
+    #[test]
+    fn errors_propagate_to_dependents() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let checked = reactor
+            .create_fallible_compute(&[CellId::Input(input)], |v| {
+                if v[0] >= 0 { Ok(v[0]) } else { Err("negative") }
+            })
+            .unwrap();
+        let plus_one = reactor
+            .create_compute(&[CellId::Compute(checked)], |v| v[0] + 1)
+            .unwrap();
+        let total = reactor
+            .create_compute(&[CellId::Input(input), CellId::Compute(plus_one)], |v| v[0] + v[1])
+            .unwrap();
+        assert!(
+            reactor
+                .add_callback(total, |v| cb.callback_called(v))
+                .is_some()
+        );
+
+        assert!(reactor.set_value(input, -1));
+        cb.expect_not_to_have_been_called();
+        let error = ComputeError { cell: checked, message: String::from("negative") };
+        assert_eq!(reactor.error(plus_one).as_ref(), Some(&error));
+        assert_eq!(reactor.error(total).as_ref(), Some(&error));
+        assert_eq!(error.to_string(), format!("compute cell {} failed: negative", checked.0.index));
+
+        assert!(reactor.set_value(input, 2));
+        cb.expect_to_have_been_called_with(5);
+        assert_eq!(reactor.error(total), None);
+    }
+
+    #[test]
+    fn compute_cells_created_on_failed_cells_start_failed() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(0);
+        let inverse = reactor
+            .create_fallible_compute(&[CellId::Input(input)], |v| {
+                100i32.checked_div(v[0]).ok_or("division by zero")
+            })
+            .unwrap();
+        let doubled = reactor
+            .create_compute(&[CellId::Compute(inverse)], |v| v[0] * 2)
+            .unwrap();
+        assert_eq!(reactor.error(doubled).map(|e| e.cell), Some(inverse));
+        assert!(reactor.set_value(input, 50));
+        assert_eq!(reactor.value(CellId::Compute(doubled)), Some(4));
+    }
+
+    #[test]
+    fn shared_reactor_handles_are_send_and_sync() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<SharedReactor<i32>>();
+        assert_send_sync::<SharedReactor<String>>();
+    }
+
+    #[test]
+    fn shared_reactor_works_like_a_reactor() {
+        let reactor = SharedReactor::new();
+        let input = reactor.create_input(1);
+        let output = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        let (sender, values) = mpsc::channel();
+        assert!(reactor.add_callback(output, move |v| sender.send(v).unwrap()).is_some());
+        assert!(reactor.set_value(input, 3));
+        assert_eq!(reactor.value(CellId::Compute(output)), Some(4));
+        assert_eq!(values.try_iter().collect::<Vec<_>>(), [4]);
+        assert_eq!(
+            reactor.remove_cell(CellId::Input(input)),
+            Err(RemoveCellError::HasDependents(vec![output]))
+        );
+        assert_eq!(reactor.remove_cell_cascade(CellId::Input(input)), Ok(vec![output]));
+        assert!(!reactor.set_value(input, 5));
+    }
+
+    #[test]
+    fn shared_reactor_can_be_set_from_many_threads() {
+        const THREADS: i64 = 8;
+        const UPDATES: i64 = 500;
+
+        let reactor = SharedReactor::new();
+        let inputs: Vec<_> = (0..THREADS).map(|_| reactor.create_input(0)).collect();
+        let cells: Vec<_> = inputs.iter().map(|&id| CellId::Input(id)).collect();
+        let total = reactor
+            .create_compute(&cells, |v| v.iter().sum::<i64>())
+            .unwrap();
+        let calls = std::sync::Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        assert!(
+            reactor
+                .add_callback(total, move |_| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                })
+                .is_some()
+        );
+
+        let workers: Vec<_> = inputs
+            .into_iter()
+            .map(|input| {
+                let reactor = reactor.clone();
+                thread::spawn(move || {
+                    for i in 1..=UPDATES {
+                        assert!(reactor.set_value(input, i));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(reactor.value(CellId::Compute(total)), Some(THREADS * UPDATES));
+        assert_eq!(calls.load(Ordering::Relaxed), (THREADS * UPDATES) as u64);
+    }
+
+    #[test]
+    fn shared_reactor_batches_are_not_interleaved() {
+        let reactor = SharedReactor::new();
+        let low = reactor.create_input(0);
+        let high = reactor.create_input(1);
+        let ordered = reactor
+            .create_compute(&[CellId::Input(low), CellId::Input(high)], |v| {
+                (v[0] < v[1]) as i32
+            })
+            .unwrap();
+        let (sender, values) = mpsc::channel();
+        assert!(reactor.add_callback(ordered, move |v| sender.send(v).unwrap()).is_some());
+
+        let workers: Vec<_> = (0..4)
+            .map(|n| {
+                let reactor = reactor.clone();
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        let base = (n * 1000 + i) * 10;
+                        assert!(reactor.set_values([(low, base), (high, base + 1)]));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(reactor.value(CellId::Compute(ordered)), Some(1));
+        assert_eq!(values.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn dependency_graph_has_a_node_per_cell() {
+        let mut reactor = Reactor::new();
+        let a = reactor.create_input(1);
+        let b = reactor.create_input(2);
+        let sum = reactor
+            .create_compute(&[CellId::Input(a), CellId::Input(b)], |v| v[0] + v[1])
+            .unwrap();
+        let half = reactor
+            .create_fallible_compute(&[CellId::Compute(sum)], |v| {
+                if v[0] % 2 == 0 { Ok(v[0] / 2) } else { Err("odd") }
+            })
+            .unwrap();
+
+        let graph = reactor.to_graph();
+        let names: Vec<_> = graph.nodes.iter().map(|node| node.name.clone()).collect();
+        let (a, b, sum, half) = (
+            format!("input{}", a.0.index),
+            format!("input{}", b.0.index),
+            format!("compute{}", sum.0.index),
+            format!("compute{}", half.0.index),
+        );
+        assert_eq!(names, [a.clone(), b.clone(), sum.clone(), half.clone()]);
+        assert_eq!(graph.node(&a).unwrap().attr("value"), Some("1"));
+        assert_eq!(graph.node(&a).unwrap().attr("shape"), Some("box"));
+        assert_eq!(graph.node(&sum).unwrap().attr("value"), Some("3"));
+        assert_eq!(graph.node(&half).unwrap().attr("value"), None);
+        assert_eq!(graph.node(&half).unwrap().attr("error"), Some("odd"));
+        assert_eq!(
+            graph.edges,
+            [
+                Edge::new(&a, &sum).with_attrs(&[("label", "0")]),
+                Edge::new(&b, &sum).with_attrs(&[("label", "1")]),
+                Edge::new(&sum, &half).with_attrs(&[("label", "0")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn dependency_graph_renders_as_dot() {
+        let mut reactor = Reactor::new();
+        let name = reactor.create_input(String::from("Ada"));
+        let greeting = reactor
+            .create_compute(&[CellId::Input(name)], |v| format!("Hi, {}", v[0]))
+            .unwrap();
+        let (input, compute) = (name.0.index, greeting.0.index);
+        assert_eq!(
+            reactor.to_dot(),
+            format!(
+                "digraph {{\n    \
+                 \"input{input}\" [\"label\"=\"input {input}\\n\\\"Ada\\\"\", \
+                 \"shape\"=\"box\", \"value\"=\"\\\"Ada\\\"\"];\n    \
+                 \"compute{compute}\" [\"label\"=\"compute {compute}\\n\\\"Hi, Ada\\\"\", \
+                 \"value\"=\"\\\"Hi, Ada\\\"\"];\n    \
+                 \"input{input}\" -> \"compute{compute}\" [\"label\"=\"0\"];\n}}"
+            )
+        );
+    }
+
+    #[test]
+    fn ids_are_scoped_to_their_reactor() {
+        let mut dummy_reactor = Reactor::new();
+        let dummy_input = dummy_reactor.create_input(1);
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        assert!(reactor.owns(CellId::Input(input)));
+        assert!(!reactor.owns(CellId::Input(dummy_input)));
+        assert_eq!(input.0.index, dummy_input.0.index);
+        assert_ne!(input, dummy_input);
+        assert_eq!(reactor.value(CellId::Input(dummy_input)), None);
+        assert!(!reactor.set_value(dummy_input, 5));
+        assert_eq!(
+            reactor.remove_cell(CellId::Input(dummy_input)),
+            Err(RemoveCellError::ForeignCell)
+        );
+        assert_eq!(
+            reactor.remove_cell_cascade(CellId::Input(dummy_input)),
+            Err(RemoveCellError::ForeignCell)
+        );
+    }
+
+    #[test]
+    fn callback_ids_are_scoped_to_their_reactor() {
+        let mut dummy_reactor = Reactor::new();
+        let dummy_input = dummy_reactor.create_input(1);
+        let dummy_output = dummy_reactor
+            .create_compute(&[CellId::Input(dummy_input)], |v| v[0])
+            .unwrap();
+        let dummy_callback = dummy_reactor.add_callback(dummy_output, |_| ()).unwrap();
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let output = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0])
+            .unwrap();
+        assert!(reactor.add_callback(output, |_| ()).is_some());
+        assert_eq!(
+            reactor.remove_callback(output, dummy_callback),
+            Err(RemoveCallbackError::ForeignCallback)
+        );
+    }
+
+    #[test]
+    fn lazy_cells_are_computed_when_asked_for() {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let mut reactor = Reactor::new_lazy();
+        let input = reactor.create_input(1);
+        let output = reactor
+            .create_compute(&[CellId::Input(input)], move |v| {
+                counter.set(counter.get() + 1);
+                v[0] * 10
+            })
+            .unwrap();
+        assert_eq!(calls.get(), 1);
+        for i in 2..5 {
+            assert!(reactor.set_value(input, i));
+        }
+        assert_eq!(calls.get(), 1);
+        assert_eq!(reactor.value(CellId::Compute(output)), Some(40));
+        assert_eq!(reactor.value(CellId::Compute(output)), Some(40));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn lazy_cells_with_callbacks_are_computed_on_change() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new_lazy();
+        let input = reactor.create_input(1);
+        let plus_one = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] + 1)
+            .unwrap();
+        let parity = reactor
+            .create_compute(&[CellId::Compute(plus_one)], |v| v[0] % 2)
+            .unwrap();
+        assert!(reactor.set_value(input, 2));
+        assert!(
+            reactor
+                .add_callback(parity, |v| cb.callback_called(v))
+                .is_some()
+        );
+        cb.expect_not_to_have_been_called();
+        assert!(reactor.set_value(input, 4));
+        cb.expect_not_to_have_been_called();
+        assert!(reactor.set_value(input, 5));
+        cb.expect_to_have_been_called_with(0);
+        assert_eq!(reactor.value(CellId::Compute(plus_one)), Some(6));
+    }
+
+    #[test]
+    fn lazy_cells_propagate_errors() {
+        let mut reactor = Reactor::new_lazy();
+        let input = reactor.create_input(1);
+        let checked = reactor
+            .create_fallible_compute(&[CellId::Input(input)], |v| {
+                if v[0] > 0 { Ok(v[0]) } else { Err("not positive") }
+            })
+            .unwrap();
+        let doubled = reactor
+            .create_compute(&[CellId::Compute(checked)], |v| v[0] * 2)
+            .unwrap();
+        assert!(reactor.set_value(input, 0));
+        assert_eq!(reactor.error(doubled).map(|e| e.cell), Some(checked));
+        assert!(reactor.set_value(input, 3));
+        assert_eq!(reactor.value(CellId::Compute(doubled)), Some(6));
+    }
+
+    #[test]
+    fn input_changes_can_be_undone_and_redone() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        let output = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] * 10)
+            .unwrap();
+        assert!(
+            reactor
+                .add_callback(output, |v| cb.callback_called(v))
+                .is_some()
+        );
+        assert!(reactor.set_value(input, 2));
+        cb.expect_to_have_been_called_with(20);
+        assert!(reactor.set_value(input, 3));
+        cb.expect_to_have_been_called_with(30);
+
+        assert!(reactor.undo());
+        cb.expect_to_have_been_called_with(20);
+        assert!(reactor.undo());
+        cb.expect_to_have_been_called_with(10);
+        assert_eq!(reactor.value(CellId::Input(input)), Some(1));
+        assert!(!reactor.undo());
+
+        assert!(reactor.redo());
+        cb.expect_to_have_been_called_with(20);
+        assert_eq!(reactor.value(CellId::Compute(output)), Some(20));
+    }
+
+    #[test]
+    fn batches_are_undone_at_once() {
+        let cb = CallbackRecorder::new();
+        let mut reactor = Reactor::new();
+        let a = reactor.create_input(1);
+        let b = reactor.create_input(2);
+        let sum = reactor
+            .create_compute(&[CellId::Input(a), CellId::Input(b)], |v| v[0] + v[1])
+            .unwrap();
+        assert!(
+            reactor
+                .add_callback(sum, |v| cb.callback_called(v))
+                .is_some()
+        );
+        assert!(reactor.set_values([(a, 10), (b, 20), (a, 30)]));
+        cb.expect_to_have_been_called_with(50);
+        assert!(reactor.undo());
+        cb.expect_to_have_been_called_with(3);
+        assert_eq!(reactor.value(CellId::Input(a)), Some(1));
+        assert_eq!(reactor.value(CellId::Input(b)), Some(2));
+        assert!(reactor.redo());
+        cb.expect_to_have_been_called_with(50);
+        assert_eq!(reactor.value(CellId::Input(a)), Some(30));
+    }
+
+    #[test]
+    fn setting_inputs_clears_the_redo_history() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        assert!(reactor.set_value(input, 2));
+        assert!(reactor.undo());
+        assert!(reactor.set_value(input, 3));
+        assert!(!reactor.redo());
+        assert!(reactor.undo());
+        assert_eq!(reactor.value(CellId::Input(input)), Some(1));
+    }
+
+    #[test]
+    fn setting_the_same_value_is_not_recorded() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(1);
+        assert!(reactor.set_value(input, 2));
+        assert!(reactor.set_value(input, 2));
+        assert!(reactor.set_values([(input, 5), (input, 2)]));
+        assert!(reactor.undo());
+        assert_eq!(reactor.value(CellId::Input(input)), Some(1));
+        assert!(!reactor.undo());
+    }
+
+    #[test]
+    fn history_length_is_limited() {
+        let mut reactor = Reactor::new();
+        let input = reactor.create_input(0);
+        for i in 1..=10 {
+            assert!(reactor.set_value(input, i));
+        }
+        reactor.set_history_limit(3);
+        while reactor.undo() {}
+        assert_eq!(reactor.value(CellId::Input(input)), Some(7));
+
+        reactor.set_history_limit(0);
+        assert!(reactor.set_value(input, 1));
+        assert!(!reactor.undo());
+    }
+
+    #[test]
+    fn removed_inputs_are_dropped_from_the_history() {
+        let mut reactor = Reactor::new();
+        let a = reactor.create_input(1);
+        let b = reactor.create_input(1);
+        assert!(reactor.set_value(a, 2));
+        assert!(reactor.set_values([(a, 3), (b, 3)]));
+        assert!(reactor.set_value(b, 4));
+        assert!(reactor.remove_cell(CellId::Input(b)).is_ok());
+        assert!(reactor.undo());
+        assert_eq!(reactor.value(CellId::Input(a)), Some(2));
+        assert!(reactor.undo());
+        assert_eq!(reactor.value(CellId::Input(a)), Some(1));
+        assert!(!reactor.undo());
+    }
+
+    #[test]
+    fn shared_reactor_supports_undo() {
+        let reactor = SharedReactor::new();
+        let input = reactor.create_input(1);
+        assert!(reactor.set_value(input, 2));
+        assert!(reactor.undo());
+        assert_eq!(reactor.value(CellId::Input(input)), Some(1));
+        assert!(reactor.redo());
+        assert_eq!(reactor.value(CellId::Input(input)), Some(2));
+    }
 }
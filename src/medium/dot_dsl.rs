@@ -16,6 +16,7 @@ macro_rules! support_attrs {
 
 pub mod graph {    
     use std::collections::HashMap;
+    use std::fmt;
     use graph_items::edge::Edge;
     use graph_items::node::Node;
 
@@ -48,6 +49,46 @@ pub mod graph {
         support_attrs!();
     }
 
+    /// Renders the graph in the Graphviz DOT language, attributes sorted by name
+    impl fmt::Display for Graph {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            writeln!(f, "digraph {{")?;
+            for (key, value) in sorted(&self.attrs) {
+                writeln!(f, "    {}={};", quote(key), quote(value))?;
+            }
+            for node in &self.nodes {
+                writeln!(f, "    {}{};", quote(&node.name), attr_list(&node.attrs))?;
+            }
+            for edge in &self.edges {
+                let (from, to) = (quote(&edge.from), quote(&edge.to));
+                writeln!(f, "    {from} -> {to}{};", attr_list(&edge.attrs))?;
+            }
+            write!(f, "}}")
+        }
+    }
+
+    fn sorted(attrs: &HashMap<String, String>) -> Vec<(&String, &String)> {
+        let mut attrs: Vec<_> = attrs.iter().collect();
+        attrs.sort();
+        attrs
+    }
+
+    fn quote(id: &str) -> String {
+        let escaped = id.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        format!("\"{escaped}\"")
+    }
+
+    fn attr_list(attrs: &HashMap<String, String>) -> String {
+        if attrs.is_empty() {
+            return String::new();
+        }
+        let attrs: Vec<_> = sorted(attrs)
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", quote(key), quote(value)))
+            .collect();
+        format!(" [{}]", attrs.join(", "))
+    }
+
     pub mod graph_items {
         pub mod node {
             use super::super::*;
@@ -256,4 +297,34 @@ mod tests {
         assert_eq!(c.attr("bat"), None);
         assert_eq!(c.attr("bim"), Some("bef"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn graph_renders_as_dot() {
+        let graph = Graph::new()
+            .with_nodes(&[
+                Node::new("a").with_attrs(&[("label", "Alpha"), ("color", "green")]),
+                Node::new("b"),
+            ])
+            .with_edges(&[Edge::new("a", "b").with_attrs(&[("style", "dashed")])])
+            .with_attrs(&[("rankdir", "LR")]);
+        assert_eq!(
+            graph.to_string(),
+            "digraph {\n\
+             \x20   \"rankdir\"=\"LR\";\n\
+             \x20   \"a\" [\"color\"=\"green\", \"label\"=\"Alpha\"];\n\
+             \x20   \"b\";\n\
+             \x20   \"a\" -> \"b\" [\"style\"=\"dashed\"];\n\
+             }"
+        );
+    }
+
+    #[test]
+    fn dot_output_escapes_quotes() {
+        let node = Node::new("say \"hi\"").with_attrs(&[("label", "a\\b")]);
+        let graph = Graph::new().with_nodes(&[node]);
+        assert_eq!(
+            graph.to_string(),
+            "digraph {\n    \"say \\\"hi\\\"\" [\"label\"=\"a\\\\b\"];\n}"
+        );
+    }
+}
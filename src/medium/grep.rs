@@ -1,6 +1,18 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::thread;
 use anyhow::Error;
 
+/// Input with a NUL byte in its first buffer of this many bytes is considered binary and skipped.
+/// `-c` still reports a count of 0 for it, so counts line up with the files given
+const BINARY_PROBE: usize = 8192;
+
+/// Name reported for `grep_reader` input by `-l`
+pub const STDIN_LABEL: &str = "(standard input)";
+
 #[derive(Debug, Default)]
 pub struct Flags {
     add_line_number: bool,
@@ -8,54 +20,1098 @@ pub struct Flags {
     ignore_case: bool,
     invert: bool,
     line_match: bool,
+    extended: bool,
+    recursive: bool,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    count: bool,
+    only_matching: bool,
+    max_count: Option<usize>,
+    before: usize,
+    after: usize,
+    error: Option<FlagError>,
 }
 
 impl Flags {
+    /// A bad flag does not fail here but is reported by `grep` and `search`
     pub fn new(flags: &[&str]) -> Self {
         let mut fs = Self::default();
-        flags.iter().for_each(|&f| match f {
-            "-n" => fs.add_line_number = true,
-            "-l" => fs.only_filenames = true,
-            "-i" => fs.ignore_case = true,
-            "-v" => fs.invert = true,
-            "-x" => fs.line_match = true,
-            _ => ()
-        });
+        let mut args = flags.iter();
+        while let Some(&f) = args.next() {
+            if let Err(e) = fs.apply(f, &mut args) {
+                fs.error.get_or_insert(e);
+            }
+        }
         fs
     }
+
+    fn apply<'a>(
+        &mut self,
+        flag: &str,
+        args: &mut impl Iterator<Item = &'a &'a str>,
+    ) -> Result<(), FlagError> {
+        match flag {
+            "-n" => self.add_line_number = true,
+            "-l" => self.only_filenames = true,
+            "-i" => self.ignore_case = true,
+            "-v" => self.invert = true,
+            "-x" => self.line_match = true,
+            "-E" => self.extended = true,
+            "-F" => self.extended = false,
+            "-r" => self.recursive = true,
+            "-c" => self.count = true,
+            "-o" => self.only_matching = true,
+            "-A" | "-B" | "-C" | "-m" => {
+                let value = args.next()
+                    .ok_or_else(|| FlagError::MissingValue(flag.to_string()))?;
+                self.set_number(flag, value)?;
+            }
+            _ => if let Some(glob) = flag.strip_prefix("--include=") {
+                self.include.push(glob.to_string());
+            } else if let Some(glob) = flag.strip_prefix("--exclude=") {
+                self.exclude.push(glob.to_string());
+            } else if let Some(name) = flag.get(..2) && ["-A", "-B", "-C", "-m"].contains(&name) {
+                self.set_number(name, &flag[2..])?;
+            } else {
+                return Err(FlagError::Unknown(flag.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_number(&mut self, flag: &str, value: &str) -> Result<(), FlagError> {
+        let n = value.parse()
+            .map_err(|_| FlagError::InvalidValue(flag.to_string(), value.to_string()))?;
+        match flag {
+            "-A" => self.after = n,
+            "-B" => self.before = n,
+            "-C" => (self.before, self.after) = (n, n),
+            _ => self.max_count = Some(n),
+        }
+        Ok(())
+    }
+
+    /// Lines of context before and after each match; `-o`, `-c` and `-l` print none
+    fn context(&self) -> (usize, usize) {
+        if self.only_matching || self.count || self.only_filenames {
+            (0, 0)
+        } else {
+            (self.before, self.after)
+        }
+    }
+
+    fn selects(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        (self.include.is_empty() || self.include.iter().any(|glob| glob_match(glob, &name)))
+            && !self.exclude.iter().any(|glob| glob_match(glob, &name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlagError {
+    Unknown(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+}
+
+impl fmt::Display for FlagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlagError::Unknown(flag) => write!(f, "unknown flag '{flag}'"),
+            FlagError::MissingValue(flag) => write!(f, "flag '{flag}' requires a value"),
+            FlagError::InvalidValue(flag, value) => {
+                write!(f, "invalid value '{value}' for flag '{flag}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlagError {}
+
+/// Lines found by `search` along with the files that could not be read
+#[derive(Debug, Default)]
+pub struct Search {
+    pub lines: Vec<String>,
+    pub errors: Vec<FileError>,
+    /// Number of selected lines, even those `-c`, `-l` or `-o` do not print
+    pub selected: usize,
+}
+
+#[derive(Debug)]
+pub struct FileError {
+    pub path: String,
+    pub error: io::Error,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
 }
 
+impl Search {
+    fn into_lines(self) -> Result<Vec<String>, Error> {
+        match self.errors.into_iter().next() {
+            Some(error) => Err(error.into()),
+            None => Ok(self.lines),
+        }
+    }
+}
+
+/// Fails on the first file that could not be read; see `search` for a lenient version
 pub fn grep(pattern: &str, flags: &Flags, files: &[&str]) -> Result<Vec<String>, Error> {
-    let pat_low = if flags.ignore_case { 
-        pattern.to_lowercase()
-    } else { String::new() };
-    
-    let mut res = vec![];
+    search(pattern, flags, files)?.into_lines()
+}
+
+/// `grep` selecting lines that match any of `patterns`
+pub fn grep_patterns(
+    patterns: &[&str],
+    flags: &Flags,
+    files: &[&str],
+) -> Result<Vec<String>, Error> {
+    search_patterns(patterns, flags, files, 1)?.into_lines()
+}
+
+/// Searches every readable file, collecting errors for the rest.
+/// Only an invalid pattern or flag fails the whole call
+pub fn search(pattern: &str, flags: &Flags, files: &[&str]) -> Result<Search, Error> {
+    search_patterns(&[pattern], flags, files, 1)
+}
+
+/// `grep` that searches files on `worker_count` threads
+pub fn grep_parallel(
+    pattern: &str,
+    flags: &Flags,
+    files: &[&str],
+    worker_count: usize,
+) -> Result<Vec<String>, Error> {
+    search_parallel(pattern, flags, files, worker_count)?.into_lines()
+}
+
+/// `search` that splits the files between `worker_count` threads.
+/// Lines and errors come out in the same order as with a single thread
+pub fn search_parallel(
+    pattern: &str,
+    flags: &Flags,
+    files: &[&str],
+    worker_count: usize,
+) -> Result<Search, Error> {
+    search_patterns(&[pattern], flags, files, worker_count)
+}
+
+/// `search_parallel` selecting lines that match any of `patterns`.
+/// Fixed strings are all matched in a single pass over each line
+pub fn search_patterns(
+    patterns: &[&str],
+    flags: &Flags,
+    files: &[&str],
+    worker_count: usize,
+) -> Result<Search, Error> {
+    if let Some(error) = &flags.error {
+        return Err(error.clone().into());
+    }
+    let matcher = Matcher::new(patterns, flags)?;
+
+    let mut paths = vec![];
     for &f in files {
-        let file = fs::read_to_string(f)?;
-        for (i, line) in file.lines().enumerate() {    
-            let found = if flags.line_match {
-                line == pattern || flags.ignore_case && line.to_lowercase() == pat_low
-            } else { 
-                line.contains(pattern) || flags.ignore_case && line.to_lowercase().contains(&pat_low)
+        collect(Path::new(f), flags, true, &mut paths);
+    }
+    let mul_files = files.len() > 1
+        || flags.recursive && files.iter().any(|f| Path::new(f).is_dir());
+
+    let searcher = |paths: &[Result<PathBuf, FileError>]| -> Vec<io::Result<(usize, Vec<String>)>> {
+        paths.iter()
+            .flatten()
+            .map(|path| search_file(&matcher, flags, path, mul_files))
+            .collect()
+    };
+    let found = match paths.len() {
+        n if n < 2 || worker_count < 2 => searcher(&paths),
+        n => thread::scope(|s| {
+            let handles: Vec<_> = paths.chunks(n.div_ceil(worker_count))
+                .map(|chunk| s.spawn(|| searcher(chunk)))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        }),
+    };
+
+    let context = flags.context() != (0, 0);
+    let mut found = found.into_iter();
+    let mut search = Search::default();
+    for path in paths {
+        let result = path.and_then(|path| {
+            found.next().unwrap()
+                .map_err(|error| FileError { path: path.to_string_lossy().into_owned(), error })
+        });
+        match result {
+            Ok((selected, lines)) => {
+                // Groups from different files are separated as well
+                if context && !lines.is_empty() && !search.lines.is_empty() {
+                    search.lines.push("--".to_string());
+                }
+                search.lines.extend(lines);
+                search.selected += selected;
+            }
+            Err(error) => search.errors.push(error),
+        }
+    }
+    Ok(search)
+}
+
+/// Expands directories for `-r` in name order, keeping errors in place.
+/// Symbolic links met while walking are not followed
+fn collect(path: &Path, flags: &Flags, top: bool, paths: &mut Vec<Result<PathBuf, FileError>>) {
+    let error = |error| FileError { path: path.to_string_lossy().into_owned(), error };
+    let file_type = if top { fs::metadata(path) } else { fs::symlink_metadata(path) };
+    match file_type.map(|meta| meta.file_type()) {
+        Ok(file_type) if file_type.is_dir() => {
+            if !flags.recursive {
+                let message = "is a directory";
+                paths.push(Err(error(io::Error::new(io::ErrorKind::IsADirectory, message))));
+                return;
+            }
+            let entries: io::Result<Vec<_>> = fs::read_dir(path)
+                .and_then(|dir| dir.map(|entry| entry.map(|e| e.path())).collect());
+            match entries {
+                Ok(mut entries) => {
+                    entries.sort();
+                    for entry in &entries {
+                        collect(entry, flags, false, paths);
+                    }
+                }
+                Err(e) => paths.push(Err(error(e))),
+            }
+        }
+        Ok(file_type) if file_type.is_symlink() => {}
+        Ok(_) => if flags.selects(path) { paths.push(Ok(path.to_path_buf())) },
+        Err(e) => paths.push(Err(error(e))),
+    }
+}
+
+/// Searches lines read from `reader`, such as stdin, the same way `grep` searches a file
+pub fn grep_reader(
+    pattern: &str,
+    flags: &Flags,
+    reader: impl BufRead,
+) -> Result<Vec<String>, Error> {
+    search_reader(pattern, flags, reader)?.into_lines()
+}
+
+/// `grep_reader` that reports a read error as `STDIN_LABEL` in `Search::errors`
+pub fn search_reader(pattern: &str, flags: &Flags, reader: impl BufRead) -> Result<Search, Error> {
+    search_reader_patterns(&[pattern], flags, reader)
+}
+
+/// `search_reader` selecting lines that match any of `patterns`
+pub fn search_reader_patterns(
+    patterns: &[&str],
+    flags: &Flags,
+    reader: impl BufRead,
+) -> Result<Search, Error> {
+    if let Some(error) = &flags.error {
+        return Err(error.clone().into());
+    }
+    let matcher = Matcher::new(patterns, flags)?;
+    let mut search = Search::default();
+    match search_lines(&matcher, flags, STDIN_LABEL, reader, false, &mut search.lines) {
+        Ok(selected) => search.selected = selected,
+        Err(error) => search.errors.push(FileError { path: STDIN_LABEL.to_string(), error }),
+    }
+    Ok(search)
+}
+
+fn search_file(
+    matcher: &Matcher,
+    flags: &Flags,
+    path: &Path,
+    mul_files: bool,
+) -> io::Result<(usize, Vec<String>)> {
+    let reader = BufReader::with_capacity(BINARY_PROBE, fs::File::open(path)?);
+    let mut res = vec![];
+    let name = path.to_string_lossy();
+    let selected = search_lines(matcher, flags, &name, reader, mul_files, &mut res)?;
+    Ok((selected, res))
+}
+
+/// Reads one line at a time, so memory use does not depend on the input size.
+/// Invalid UTF-8 is replaced with U+FFFD rather than failing the search.
+/// Returns the number of selected lines
+fn search_lines(
+    matcher: &Matcher,
+    flags: &Flags,
+    name: &str,
+    mut reader: impl BufRead,
+    mul_files: bool,
+    res: &mut Vec<String>,
+) -> io::Result<usize> {
+    let probe = reader.fill_buf()?;
+    if probe[..probe.len().min(BINARY_PROBE)].contains(&0) {
+        if flags.count && !flags.only_filenames {
+            res.push(count_line(name, 0, mul_files));
+        }
+        return Ok(0);
+    }
+
+    let (before_len, after_len) = flags.context();
+    let context = before_len > 0 || after_len > 0;
+    let mut before: VecDeque<(usize, String)> = VecDeque::with_capacity(before_len + 1);
+    let mut after = 0;
+    let mut last = None;
+    let mut selected = 0;
+    let mut buf = vec![];
+
+    for i in 0.. {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        let line = match buf.strip_suffix(b"\n") {
+            Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+            None => &buf,
+        };
+        let line = String::from_utf8_lossy(line);
+        let line = line.as_ref();
+
+        if flags.max_count.is_some_and(|max| selected >= max) {
+            if after == 0 { break; }
+            res.push(format(flags, name, line, i, mul_files, '-'));
+            after -= 1;
+            continue;
+        }
+
+        if matcher.is_match(line, flags) ^ flags.invert {
+            selected += 1;
+            if flags.only_filenames {
+                res.push(name.to_string());
+                return Ok(selected);
+            }
+            if flags.count { continue; }
+
+            let first = before.front().map_or(i, |&(n, _)| n);
+            if context && last.is_some_and(|n| first > n + 1) {
+                res.push("--".to_string());
+            }
+            for (n, line) in before.drain(..) {
+                res.push(format(flags, name, &line, n, mul_files, '-'));
+            }
+            if flags.only_matching {
+                if !flags.invert {
+                    matcher.for_each_match(line, flags, |part| {
+                        res.push(format(flags, name, part, i, mul_files, ':'))
+                    });
+                }
+            } else {
+                res.push(format(flags, name, line, i, mul_files, ':'));
+            }
+            last = Some(i);
+            after = after_len;
+        } else if after > 0 {
+            res.push(format(flags, name, line, i, mul_files, '-'));
+            last = Some(i);
+            after -= 1;
+        } else if before_len > 0 {
+            before.push_back((i, line.to_string()));
+            if before.len() > before_len {
+                before.pop_front();
+            }
+        }
+    }
+
+    if flags.count && !flags.only_filenames {
+        res.push(count_line(name, selected, mul_files));
+    }
+    Ok(selected)
+}
+
+fn count_line(name: &str, selected: usize, mul_files: bool) -> String {
+    if mul_files { format!("{name}:{selected}") } else { selected.to_string() }
+}
+
+/// Shell-style wildcards: `*`, `?` and `[...]` sets, negated with `!` or `^`
+fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut g, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        let step = match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, n));
+                g += 1;
+                continue;
+            }
+            Some('?') => Some(g + 1),
+            Some('[') => glob_set(&glob[g + 1..], name[n]).map(|len| g + 1 + len),
+            Some(&c) => (c == name[n]).then_some(g + 1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(next), _) => (g, n) = (next, n + 1),
+            (None, Some((star, from))) => {
+                (g, n) = (star + 1, from + 1);
+                backtrack = Some((star, from + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the set that follows a `[`,
+/// returning the length of the set including the closing `]`
+fn glob_set(set: &[char], c: char) -> Option<usize> {
+    let negated = matches!(set.first(), Some('!' | '^'));
+    let mut i = negated as usize;
+    let mut found = false;
+    let mut first = true;
+    while let Some(&lo) = set.get(i) {
+        if lo == ']' && !first {
+            return (found != negated).then_some(i + 1);
+        }
+        first = false;
+        if set.get(i + 1) == Some(&'-') && set.get(i + 2).is_some_and(|&hi| hi != ']') {
+            found |= lo <= c && c <= set[i + 2];
+            i += 3;
+        } else {
+            found |= lo == c;
+            i += 1;
+        }
+    }
+    // An unclosed `[` is an ordinary character
+    (c == '[').then_some(0)
+}
+
+/// `sep` is `:` for selected lines and `-` for context lines
+fn format(
+    flags: &Flags,
+    filename: &str,
+    line: &str,
+    line_num: usize,
+    mul_files: bool,
+    sep: char,
+) -> String {
+    match (flags.add_line_number, mul_files) {
+        (true, true) => format!("{}{sep}{}{sep}{}", filename, line_num + 1, line),
+        (true, false) => format!("{}{sep}{}", line_num + 1, line),
+        (false, true) => format!("{}{sep}{}", filename, line),
+        (false, false) => line.to_string(),
+    }
+}
+
+/// Fixed strings are the default; a regex is only compiled for `-E`.
+/// Several fixed strings share one Aho-Corasick automaton
+enum Matcher {
+    Fixed { pattern: String, pat_low: String },
+    Set(AhoCorasick),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(patterns: &[&str], flags: &Flags) -> Result<Self, RegexError> {
+        Ok(match patterns {
+            _ if flags.extended => {
+                let regex = Regex::any_of(patterns)?;
+                Matcher::Regex(if flags.ignore_case { regex.with_ignore_case() } else { regex })
+            }
+            &[pattern] => {
+                let pat_low = if flags.ignore_case {
+                    pattern.to_lowercase()
+                } else { String::new() };
+                Matcher::Fixed { pattern: pattern.to_string(), pat_low }
+            }
+            _ => Matcher::Set(AhoCorasick::new(patterns, flags.ignore_case)),
+        })
+    }
+
+    fn is_match(&self, line: &str, flags: &Flags) -> bool {
+        match self {
+            Matcher::Fixed { pattern, pat_low } => if flags.line_match {
+                line == pattern || flags.ignore_case && line.to_lowercase() == *pat_low
+            } else {
+                line.contains(pattern.as_str())
+                    || flags.ignore_case && line.to_lowercase().contains(pat_low.as_str())
+            },
+            Matcher::Set(set) => if flags.line_match {
+                set.find_at(line, 0) == Some((0, line.len()))
+            } else {
+                set.is_match(line)
+            },
+            Matcher::Regex(regex) => if flags.line_match {
+                regex.find(line) == Some((0, line.len()))
+            } else {
+                regex.is_match(line)
+            },
+        }
+    }
+
+    fn find_at(&self, line: &str, start: usize, flags: &Flags) -> Option<(usize, usize)> {
+        if flags.line_match {
+            return (start == 0 && self.is_match(line, flags)).then_some((0, line.len()));
+        }
+        match self {
+            Matcher::Fixed { pattern, .. } if !flags.ignore_case => {
+                let from = start + line[start..].find(pattern.as_str())?;
+                Some((from, from + pattern.len()))
+            }
+            Matcher::Fixed { pattern, .. } => line[start..].char_indices().find_map(|(i, _)| {
+                let mut rest = line[start + i..].char_indices();
+                pattern.chars()
+                    .all(|p| rest.next().is_some_and(|(_, c)| lower(c) == lower(p)))
+                    .then(|| {
+                        let len = rest.next().map_or(line.len() - start - i, |(n, _)| n);
+                        (start + i, start + i + len)
+                    })
+            }),
+            Matcher::Set(set) => set.find_at(line, start),
+            Matcher::Regex(regex) => regex.find_at(line, start),
+        }
+    }
+
+    /// Calls `f` with every non-empty match in `line`, left to right
+    fn for_each_match<'a>(&self, line: &'a str, flags: &Flags, mut f: impl FnMut(&'a str)) {
+        let mut start = 0;
+        while start <= line.len() && let Some((from, to)) = self.find_at(line, start, flags) {
+            if from < to {
+                f(&line[from..to]);
+                start = to;
+            } else {
+                start = to + line[to..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegexError {
+    UnmatchedParen(usize),
+    UnclosedClass(usize),
+    InvalidRange(usize),
+    NothingToRepeat(usize),
+    TrailingBackslash,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegexError::UnmatchedParen(at) => write!(f, "unmatched parenthesis at {at}"),
+            RegexError::UnclosedClass(at) => write!(f, "unclosed character class at {at}"),
+            RegexError::InvalidRange(at) => write!(f, "invalid character range at {at}"),
+            RegexError::NothingToRepeat(at) => write!(f, "nothing to repeat at {at}"),
+            RegexError::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+/// Extended regular expression compiled to a Thompson NFA.
+/// Matches are leftmost-longest, as in POSIX grep
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat(Box<Node>, Repeat),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repeat {
+    ZeroOrMore,
+    OneOrMore,
+    ZeroOrOne,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    const DIGIT: &[(char, char)] = &[('0', '9')];
+    const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+    const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+    fn contains(&self, c: char, ignore_case: bool) -> bool {
+        let within = |c: char| self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        let found = within(c) || ignore_case && (within(lower(c)) || within(upper(c)));
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += c.is_some() as usize;
+        c
+    }
+
+    fn alternation(&mut self) -> Result<Node, RegexError> {
+        let mut alts = vec![self.concatenation()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alts.push(self.concatenation()?);
+        }
+        Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Node::Alt(alts) })
+    }
+
+    fn concatenation(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() && c != '|' && c != ')' {
+            nodes.push(self.repetition()?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repetition(&mut self) -> Result<Node, RegexError> {
+        let mut node = self.atom()?;
+        while let Some(c) = self.peek() {
+            let repeat = match c {
+                '*' => Repeat::ZeroOrMore,
+                '+' => Repeat::OneOrMore,
+                '?' => Repeat::ZeroOrOne,
+                _ => break,
+            };
+            self.pos += 1;
+            node = Node::Repeat(Box::new(node), repeat);
+        }
+        Ok(node)
+    }
+
+    fn atom(&mut self) -> Result<Node, RegexError> {
+        let at = self.pos;
+        Ok(match self.next().unwrap() {
+            '(' => {
+                let node = self.alternation()?;
+                if self.next() != Some(')') {
+                    return Err(RegexError::UnmatchedParen(at));
+                }
+                node
+            }
+            '*' | '+' | '?' => return Err(RegexError::NothingToRepeat(at)),
+            '[' => Node::Class(self.class(at)?),
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '\\' => {
+                let c = self.next().ok_or(RegexError::TrailingBackslash)?;
+                match Self::shorthand(c) {
+                    Some(ranges) => Node::Class(Class {
+                        ranges: ranges.to_vec(),
+                        negated: c.is_ascii_uppercase(),
+                    }),
+                    None => Node::Char(Self::escaped(c)),
+                }
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    fn class(&mut self, at: usize) -> Result<Class, RegexError> {
+        let negated = self.peek() == Some('^');
+        self.pos += negated as usize;
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                None => return Err(RegexError::UnclosedClass(at)),
+                Some(']') if !first => break,
+                Some('\\') => {
+                    let c = self.next().ok_or(RegexError::TrailingBackslash)?;
+                    if let Some(class) = Self::shorthand(c) && c.is_ascii_lowercase() {
+                        ranges.extend_from_slice(class);
+                        first = false;
+                        continue;
+                    }
+                    Self::escaped(c)
+                }
+                Some(c) => c,
             };
-            
-            if found ^ flags.invert {
-                res.push(format(flags, f, line, i, files.len() > 1));
-                if flags.only_filenames { break; }
+            first = false;
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                let dash = self.pos;
+                self.pos += 1;
+                let hi = match self.next().unwrap() {
+                    '\\' => Self::escaped(self.next().ok_or(RegexError::TrailingBackslash)?),
+                    hi => hi,
+                };
+                if hi < c {
+                    return Err(RegexError::InvalidRange(dash));
+                }
+                ranges.push((c, hi));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Class { ranges, negated })
+    }
+
+    fn shorthand(c: char) -> Option<&'static [(char, char)]> {
+        match c.to_ascii_lowercase() {
+            'd' => Some(Class::DIGIT),
+            'w' => Some(Class::WORD),
+            's' => Some(Class::SPACE),
+            _ => None,
+        }
+    }
+
+    fn escaped(c: char) -> char {
+        match c {
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            c => c,
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        Self::any_of(&[pattern])
+    }
+
+    /// Matches wherever any of `patterns` would, as if they were joined with `|`.
+    /// Error positions refer to the pattern that failed to parse
+    pub fn any_of(patterns: &[&str]) -> Result<Self, RegexError> {
+        let mut alts = vec![];
+        for pattern in patterns {
+            let mut parser = Parser { chars: pattern.chars().collect(), pos: 0 };
+            alts.push(parser.alternation()?);
+            if parser.pos < parser.chars.len() {
+                return Err(RegexError::UnmatchedParen(parser.pos));
+            }
+        }
+        let node = if alts.len() == 1 { alts.pop().unwrap() } else { Node::Alt(alts) };
+        let mut program = vec![];
+        Self::compile(&node, &mut program);
+        program.push(Inst::Match);
+        Ok(Self { program, ignore_case: false })
+    }
+
+    pub fn with_ignore_case(mut self) -> Self {
+        self.ignore_case = true;
+        self
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// Byte range of the leftmost-longest match
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.find_at(text, 0)
+    }
+
+    /// Like `find`, but the search starts at byte `start`.
+    /// `^` still only matches at the beginning of `text`
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut found: Option<(usize, usize)> = None;
+        let mut chars = text[start..].chars();
+        let mut pos = start;
+
+        loop {
+            if found.is_none() {
+                self.add_thread(&mut current, 0, pos, pos, text);
+            }
+            if current.list.is_empty() {
+                break;
+            }
+            let c = chars.next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+            next.clear();
+
+            for &(pc, from) in &current.list {
+                if found.is_some_and(|(start, _)| from > start) {
+                    continue;
+                }
+                let step = match (&self.program[pc], c) {
+                    (Inst::Match, _) => {
+                        let longer = |(start, end)| from < start || from == start && pos > end;
+                        if found.is_none_or(longer) {
+                            found = Some((from, pos));
+                        }
+                        false
+                    }
+                    (Inst::Char(expected), Some(c)) => *expected == c
+                        || self.ignore_case && lower(*expected) == lower(c),
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class(class), Some(c)) => class.contains(c, self.ignore_case),
+                    _ => false,
+                };
+                if step {
+                    self.add_thread(&mut next, pc + 1, from, next_pos, text);
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            pos = next_pos;
+        }
+        found
+    }
+
+    /// Follows the epsilon transitions from `pc`, queueing every consuming instruction reached
+    fn add_thread(&self, threads: &mut Threads, pc: usize, from: usize, pos: usize, text: &str) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            match self.program[pc] {
+                Inst::Jmp(to) => stack.push(to),
+                Inst::Split(first, second) => stack.extend([second, first]),
+                Inst::Start => if pos == 0 { stack.push(pc + 1) },
+                Inst::End => if pos == text.len() { stack.push(pc + 1) },
+                _ => threads.list.push((pc, from)),
+            }
+        }
+    }
+
+    fn compile(node: &Node, program: &mut Vec<Inst>) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => program.push(Inst::Char(*c)),
+            Node::Any => program.push(Inst::Any),
+            Node::Class(class) => program.push(Inst::Class(class.clone())),
+            Node::Start => program.push(Inst::Start),
+            Node::End => program.push(Inst::End),
+            Node::Concat(nodes) => nodes.iter().for_each(|node| Self::compile(node, program)),
+            // An empty alternation, from an empty pattern set, matches nothing
+            Node::Alt(alts) if alts.is_empty() => {
+                program.push(Inst::Class(Class { ranges: vec![], negated: false }));
+            }
+            Node::Alt(alts) => {
+                let mut jumps = vec![];
+                for (i, alt) in alts.iter().enumerate() {
+                    if i + 1 == alts.len() {
+                        Self::compile(alt, program);
+                        break;
+                    }
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    Self::compile(alt, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jmp(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                let end = program.len();
+                jumps.into_iter().for_each(|jump| program[jump] = Inst::Jmp(end));
+            }
+            Node::Repeat(node, Repeat::ZeroOrMore) => {
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                Self::compile(node, program);
+                program.push(Inst::Jmp(split));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            Node::Repeat(node, Repeat::OneOrMore) => {
+                let start = program.len();
+                Self::compile(node, program);
+                program.push(Inst::Split(start, program.len() + 1));
+            }
+            Node::Repeat(node, Repeat::ZeroOrOne) => {
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                Self::compile(node, program);
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+        }
+    }
+}
+
+/// Aho-Corasick automaton over chars, finding any number of fixed strings in one pass
+#[derive(Debug, Clone)]
+struct AhoCorasick {
+    states: Vec<State>,
+    ignore_case: bool,
+    max_len: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    next: HashMap<char, usize>,
+    fail: usize,
+    /// Length in chars of the longest pattern ending here, following fail links too
+    longest: Option<usize>,
+}
+
+impl AhoCorasick {
+    fn new(patterns: &[&str], ignore_case: bool) -> Self {
+        let mut states = vec![State::default()];
+        let mut max_len = 0;
+
+        for pattern in patterns {
+            let mut s = 0;
+            let mut len = 0;
+            for c in pattern.chars() {
+                let c = if ignore_case { lower(c) } else { c };
+                s = match states[s].next.get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        states.push(State::default());
+                        let next = states.len() - 1;
+                        states[s].next.insert(c, next);
+                        next
+                    }
+                };
+                len += 1;
+            }
+            states[s].longest = Some(len);
+            max_len = max_len.max(len);
+        }
+
+        // Breadth-first, so every fail target is complete before it is used
+        let mut queue: VecDeque<usize> = states[0].next.values().copied().collect();
+        while let Some(s) = queue.pop_front() {
+            let edges: Vec<(char, usize)> = states[s].next.iter().map(|(&c, &n)| (c, n)).collect();
+            for (c, next) in edges {
+                let mut f = states[s].fail;
+                let fail = loop {
+                    if let Some(&target) = states[f].next.get(&c) {
+                        break target;
+                    }
+                    if f == 0 {
+                        break 0;
+                    }
+                    f = states[f].fail;
+                };
+                states[next].fail = fail;
+                states[next].longest = states[next].longest.max(states[fail].longest);
+                queue.push_back(next);
+            }
+        }
+
+        Self { states, ignore_case, max_len }
+    }
+
+    fn step(&self, mut s: usize, c: char) -> usize {
+        let c = if self.ignore_case { lower(c) } else { c };
+        loop {
+            if let Some(&next) = self.states[s].next.get(&c) {
+                return next;
+            }
+            if s == 0 {
+                return 0;
             }
+            s = self.states[s].fail;
         }
     }
-    Ok(res)
+
+    fn is_match(&self, text: &str) -> bool {
+        let mut s = 0;
+        let mut chars = text.chars();
+        loop {
+            if self.states[s].longest.is_some() {
+                return true;
+            }
+            match chars.next() {
+                Some(c) => s = self.step(s, c),
+                None => return false,
+            }
+        }
+    }
+
+    /// Byte range of the leftmost-longest match starting at or after byte `start`
+    fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        // Byte offsets of the char positions seen so far, to turn char lengths into ranges
+        let mut offsets = vec![start];
+        let mut found: Option<(usize, usize)> = None;
+        let mut s = 0;
+        let mut chars = text[start..].char_indices();
+
+        loop {
+            let end = offsets.len() - 1;
+            if let Some(len) = self.states[s].longest {
+                let longer = |(from, to)| end - len < from || end - len == from && end > to;
+                if found.is_none_or(longer) {
+                    found = Some((end - len, end));
+                }
+            }
+            // Later matches would start after the one found
+            if found.is_some_and(|(from, _)| end + 1 > from + self.max_len) {
+                break;
+            }
+            let Some((i, c)) = chars.next() else { break };
+            offsets.push(start + i + c.len_utf8());
+            s = self.step(s, c);
+        }
+        found.map(|(from, to)| (offsets[from], offsets[to]))
+    }
 }
 
-fn format(flags: &Flags, filename: &str, line: &str, line_num: usize, mul_files: bool) -> String {
-    match (flags.only_filenames, flags.add_line_number, mul_files) {
-        (true, _, _) => filename.to_string(),
-        (false, true, true) => format!("{}:{}:{}", filename, line_num + 1, line),
-        (false, true, false) => format!("{}:{}", line_num + 1, line),
-        (false, false, true) => format!("{}:{}", filename, line),
-        (false, false, false) => line.to_string(),
+/// Set of NFA states for one step of the simulation, in priority order
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Self { list: Vec::with_capacity(len), seen: vec![false; len] }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        !std::mem::replace(&mut self.seen[pc], true)
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.iter_mut().for_each(|seen| *seen = false);
     }
 }
 
@@ -449,6 +1505,449 @@ mod tests {
         assert_eq!(actual, expected);
     }
     
+    #[test]
+    fn regex_literals_and_classes() {
+        let regex = Regex::new(r"h[ae]llo\s\d+").unwrap();
+        assert!(regex.is_match("say hello 42"));
+        assert!(regex.is_match("hallo\t7"));
+        assert!(!regex.is_match("hullo 42"));
+        assert!(!regex.is_match("hello x"));
+        assert!(Regex::new("[^a-z]").unwrap().is_match("abc1"));
+        assert!(!Regex::new("[^a-z]").unwrap().is_match("abc"));
+        assert!(Regex::new("[]x-]").unwrap().is_match("-"));
+        assert!(Regex::new(r"a\.b").unwrap().is_match("a.b"));
+        assert!(!Regex::new(r"a\.b").unwrap().is_match("axb"));
+    }
+
+    #[test]
+    fn regex_anchors_alternation_and_groups() {
+        let regex = Regex::new("^(cat|dog)s?$").unwrap();
+        assert!(regex.is_match("cat"));
+        assert!(regex.is_match("dogs"));
+        assert!(!regex.is_match("cats!"));
+        assert!(!regex.is_match("a cat"));
+        assert!(Regex::new("a(b|)c").unwrap().is_match("ac"));
+        assert!(Regex::new("x|^y").unwrap().is_match("yes"));
+        assert!(!Regex::new("x|^y").unwrap().is_match("my"));
+    }
+
+    #[test]
+    fn regex_finds_leftmost_longest_match() {
+        assert_eq!(Regex::new("a+").unwrap().find("baaab"), Some((1, 4)));
+        assert_eq!(Regex::new("a|ab|abc").unwrap().find("xabcd"), Some((1, 4)));
+        assert_eq!(Regex::new("a.*b|c").unwrap().find("acb"), Some((0, 3)));
+        assert_eq!(Regex::new("x*").unwrap().find("abc"), Some((0, 0)));
+        assert_eq!(Regex::new("b$").unwrap().find_at("abab", 2), Some((3, 4)));
+        assert_eq!(Regex::new("^a").unwrap().find_at("aa", 1), None);
+        assert_eq!(Regex::new("é+").unwrap().find("café"), Some((3, 5)));
+    }
+
+    #[test]
+    fn regex_ignore_case() {
+        let regex = Regex::new("^[a-c]+ Месяц$").unwrap().with_ignore_case();
+        assert!(regex.is_match("ABC месяц"));
+        assert!(!Regex::new("[a-c]").unwrap().is_match("B"));
+    }
+
+    #[test]
+    fn regex_rejects_malformed_patterns() {
+        assert_eq!(Regex::new("(ab").unwrap_err(), RegexError::UnmatchedParen(0));
+        assert_eq!(Regex::new("ab)").unwrap_err(), RegexError::UnmatchedParen(2));
+        assert_eq!(Regex::new("a[bc").unwrap_err(), RegexError::UnclosedClass(1));
+        assert_eq!(Regex::new("[z-a]").unwrap_err(), RegexError::InvalidRange(2));
+        assert_eq!(Regex::new("a|*").unwrap_err(), RegexError::NothingToRepeat(2));
+        assert_eq!(Regex::new("a\\").unwrap_err(), RegexError::TrailingBackslash);
+    }
+
+    #[test]
+    fn extended_regex_flag() {
+        let pattern = "^Of (Atreus|Oreb)";
+        let flags = Flags::new(&["-n", "-E"]);
+        let files = Files::new(&["4-1-iliad.txt", "4-1-paradise-lost.txt"]);
+        let actual = grep(pattern, &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "4-1-iliad.txt:9:Of Atreus, Agamemnon, King of men.",
+            "4-1-paradise-lost.txt:7:Of Oreb, or of Sinai, didst inspire",
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn extended_regex_with_other_flags() {
+        let flags = Flags::new(&["-E", "-x", "-i"]);
+        let files = Files::new(&["4-2-midsummer-night.txt"]);
+        let actual = grep("if i .* demetrius.", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &["If I refuse to wed Demetrius."];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-E", "-v"]);
+        let actual = grep("[,;.]$", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &["But I beseech your grace that I may know"];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn fixed_strings_are_the_default() {
+        let flags = Flags::new(&[]);
+        let files = Files::new(&["4-3-iliad.txt"]);
+        assert!(grep("(so stood", &flags, files.as_ref()).unwrap().len() == 1);
+        assert!(grep("(so stood", &Flags::new(&["-E"]), files.as_ref()).is_err());
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("*.txt", "iliad.txt"));
+        assert!(!glob_match("*.txt", "iliad.txt.bak"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("?-[0-9].t[!a]t", "x-7.txt"));
+        assert!(!glob_match("?-[0-9].t[!x]t", "x-7.txt"));
+        assert!(glob_match("[a", "[a"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn directory_without_recursive_flag_is_reported() {
+        let dir = Dir::new("5-1-dir", &["iliad.txt"]);
+        let flags = Flags::new(&[]);
+        let search = search("Achilles", &flags, &[dir.path]).unwrap();
+        assert!(search.lines.is_empty());
+        assert_eq!(search.errors.len(), 1);
+        assert_eq!(search.errors[0].path, "5-1-dir");
+        assert_eq!(search.errors[0].error.kind(), std::io::ErrorKind::IsADirectory);
+    }
+
+    #[test]
+    fn recursive_search_walks_directories_in_order() {
+        let names = ["b/iliad.txt", "a/paradise-lost.txt", "midsummer-night.txt"];
+        let dir = Dir::new("5-2-dir", &names);
+        let flags = Flags::new(&["-r", "-n", "-E"]);
+        let actual = grep("^Of|modest", &flags, &[dir.path]).unwrap();
+        let expected: &[&str] = &[
+            "5-2-dir/a/paradise-lost.txt:1:Of Mans First Disobedience, and the Fruit",
+            "5-2-dir/a/paradise-lost.txt:2:Of that Forbidden Tree, whose mortal tast",
+            "5-2-dir/a/paradise-lost.txt:7:Of Oreb, or of Sinai, didst inspire",
+            "5-2-dir/b/iliad.txt:9:Of Atreus, Agamemnon, King of men.",
+            "5-2-dir/midsummer-night.txt:3:Nor how it may concern my modesty,",
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let dir = Dir::new("5-3-dir", &["iliad.txt", "iliad.md", "old/iliad.txt"]);
+        let flags = Flags::new(&["-r", "-l", "--include=*.txt", "--exclude=[o]*"]);
+        let actual = grep("Achilles", &flags, &[dir.path]).unwrap();
+        let expected: &[&str] = &["5-3-dir/iliad.txt", "5-3-dir/old/iliad.txt"];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-r", "-l", "--exclude=*.md", "--exclude=old"]);
+        let actual = grep("Achilles", &flags, &[dir.path]).unwrap();
+        let expected: &[&str] = &["5-3-dir/iliad.txt", "5-3-dir/old/iliad.txt"];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn binary_files_are_skipped() {
+        let dir = Dir::new("5-4-dir", &["iliad.txt"]);
+        std::fs::write("5-4-dir/data.bin", b"Achilles\0\x01\x02").unwrap();
+        let flags = Flags::new(&["-r", "-l"]);
+        let actual = grep("Achilles", &flags, &[dir.path]).unwrap();
+        let expected: &[&str] = &["5-4-dir/iliad.txt"];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-c"]);
+        let actual = grep("Achilles", &flags, &["5-4-dir/iliad.txt", "5-4-dir/data.bin"]).unwrap();
+        let expected: &[&str] = &["5-4-dir/iliad.txt:2", "5-4-dir/data.bin:0"];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn unreadable_file_does_not_abort_search() {
+        let files = Files::new(&["5-5-iliad.txt", "5-5-paradise-lost.txt"]);
+        let dir = Dir::new("5-5-dir", &["iliad.txt"]);
+        let paths = ["5-5-iliad.txt", "5-5-missing.txt", dir.path, files.as_ref()[1]];
+        let flags = Flags::new(&["-l"]);
+        let search = search("Of", &flags, &paths).unwrap();
+
+        assert_eq!(search.lines, ["5-5-iliad.txt", "5-5-paradise-lost.txt"]);
+        let errors: Vec<_> = search.errors.iter()
+            .map(|e| (e.path.as_str(), e.error.kind()))
+            .collect();
+        assert_eq!(errors, [
+            ("5-5-missing.txt", std::io::ErrorKind::NotFound),
+            ("5-5-dir", std::io::ErrorKind::IsADirectory),
+        ]);
+        assert!(grep("Of", &flags, &paths).is_err());
+    }
+
+    #[test]
+    fn context_lines_with_group_separators() {
+        let files = Files::new(&["6-1-iliad.txt"]);
+        let flags = Flags::new(&["-n", "-A", "1"]);
+        let actual = grep("Achilles", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "1:Achilles sing, O Goddess! Peleus' son;",
+            "2-His wrath pernicious, who ten thousand woes",
+            "--",
+            "8:The noble Chief Achilles from the son",
+            "9-Of Atreus, Agamemnon, King of men.",
+        ];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-n", "-E", "-A1"]);
+        let actual = grep("^(Illustrious|To)", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "4:Illustrious into Ades premature,",
+            "5-And Heroes gave (so stood the will of Jove)",
+            "6:To dogs and to all ravening fowls a prey,",
+            "7-When fierce dispute had separated once",
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn context_before_and_around_across_files() {
+        let files = Files::new(&["6-2-iliad.txt", "6-2-paradise-lost.txt"]);
+        let flags = Flags::new(&["-B", "1"]);
+        let actual = grep("Of ", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "6-2-iliad.txt-The noble Chief Achilles from the son",
+            "6-2-iliad.txt:Of Atreus, Agamemnon, King of men.",
+            "--",
+            "6-2-paradise-lost.txt:Of Mans First Disobedience, and the Fruit",
+            "6-2-paradise-lost.txt:Of that Forbidden Tree, whose mortal tast",
+            "--",
+            "6-2-paradise-lost.txt-Sing Heav'nly Muse, that on the secret top",
+            "6-2-paradise-lost.txt:Of Oreb, or of Sinai, didst inspire",
+        ];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-n", "-C", "1"]);
+        let actual = grep("Ades", &flags, &[files.as_ref()[0]]).unwrap();
+        let expected: &[&str] = &[
+            "3-Caused to Achaia's host, sent many a soul",
+            "4:Illustrious into Ades premature,",
+            "5-And Heroes gave (so stood the will of Jove)",
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn count_flag() {
+        let files = Files::new(&["6-3-iliad.txt", "6-3-midsummer-night.txt"]);
+        let flags = Flags::new(&["-c"]);
+        let actual = grep("the", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &["6-3-iliad.txt:2", "6-3-midsummer-night.txt:0"];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-c", "-v", "-m", "3"]);
+        let actual = grep("the", &flags, &[files.as_ref()[0]]).unwrap();
+        let expected: &[&str] = &["3"];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn only_matching_flag() {
+        let files = Files::new(&["6-4-iliad.txt"]);
+        let flags = Flags::new(&["-o", "-n", "-i"]);
+        let actual = grep("ACHI", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &["1:Achi", "8:Achi"];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-o", "-E", "-A", "2"]);
+        let actual = grep("A[a-z]+|x*", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "Achilles", "Achaia", "Ades", "And", "Achilles", "Atreus", "Agamemnon",
+        ];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-o", "-v"]);
+        assert!(grep("Achilles", &flags, files.as_ref()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn max_count_flag() {
+        let files = Files::new(&["6-5-iliad.txt", "6-5-paradise-lost.txt"]);
+        let flags = Flags::new(&["-m1", "-A", "1"]);
+        let actual = grep("Of", &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "6-5-iliad.txt:Of Atreus, Agamemnon, King of men.",
+            "--",
+            "6-5-paradise-lost.txt:Of Mans First Disobedience, and the Fruit",
+            "6-5-paradise-lost.txt-Of that Forbidden Tree, whose mortal tast",
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn bad_flags_are_reported() {
+        let files = Files::new(&["6-6-iliad.txt"]);
+        let error = |flags: &[&str]| grep("Achilles", &Flags::new(flags), files.as_ref())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error(&["-n", "-z", "-q"]), "unknown flag '-z'");
+        assert_eq!(error(&["-A"]), "flag '-A' requires a value");
+        assert_eq!(error(&["-Cx"]), "invalid value 'x' for flag '-C'");
+        assert_eq!(error(&["-m", "-1"]), "invalid value '-1' for flag '-m'");
+    }
+
+    #[test]
+    fn invalid_utf8_is_matched_lossily() {
+        std::fs::write("7-1-latin1.txt", b"caf\xe9 au lait\r\nth\xe9\r\n").unwrap();
+        let flags = Flags::new(&["-n"]);
+        let actual = grep("au lait", &flags, &["7-1-latin1.txt"]);
+        std::fs::remove_file("7-1-latin1.txt").unwrap();
+        let expected: &[&str] = &["1:caf\u{FFFD} au lait"];
+        assert_eq!(actual.unwrap(), expected);
+    }
+
+    #[test]
+    fn grep_reader_searches_any_buffered_input() {
+        let input = std::io::Cursor::new(ILIAD_CONTENT);
+        let flags = Flags::new(&["-n", "-i"]);
+        let actual = grep_reader("achilles", &flags, input).unwrap();
+        let expected: &[&str] = &[
+            "1:Achilles sing, O Goddess! Peleus' son;",
+            "8:The noble Chief Achilles from the son",
+        ];
+        assert_eq!(actual, expected);
+
+        let flags = Flags::new(&["-l"]);
+        let actual = grep_reader("Atreus", &flags, ILIAD_CONTENT.as_bytes()).unwrap();
+        assert_eq!(actual, [STDIN_LABEL]);
+        assert!(grep_reader("Atreus", &flags, &b"Atreus\0"[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lines_longer_than_the_buffer_are_read_whole() {
+        let long = "a".repeat(3 * BINARY_PROBE) + "Agamemnon" + &"b".repeat(BINARY_PROBE);
+        let text = format!("x\n{long}\ny");
+        let input = std::io::BufReader::with_capacity(16, text.as_bytes());
+        let flags = Flags::new(&["-n", "-o"]);
+        let actual = grep_reader("Agamemnon", &flags, input).unwrap();
+        assert_eq!(actual, ["2:Agamemnon"]);
+    }
+
+    #[test]
+    fn parallel_search_keeps_file_order() {
+        let texts = ["iliad.txt", "midsummer-night.txt", "paradise-lost.txt"];
+        let names: Vec<String> = (0..12).map(|i| format!("{i:02}-{}", texts[i % 3])).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let dir = Dir::new("8-1-dir", &names);
+        let files = ["8-1-missing.txt", dir.path, "8-1-missing-too.txt"];
+        let flags = Flags::new(&["-r", "-n", "-C", "1", "-i"]);
+
+        let expected = search("of", &flags, &files).unwrap();
+        assert!(expected.lines[0].starts_with("8-1-dir/00-iliad.txt-4-"));
+        assert!(expected.lines.last().unwrap().starts_with("8-1-dir/11-paradise-lost.txt-8-"));
+        for worker_count in [2, 3, 5, 64] {
+            let actual = search_parallel("of", &flags, &files, worker_count).unwrap();
+            assert_eq!(actual.lines, expected.lines);
+            let paths: Vec<_> = actual.errors.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(paths, ["8-1-missing.txt", "8-1-missing-too.txt"]);
+        }
+    }
+
+    #[test]
+    fn grep_parallel_matches_grep() {
+        let names = ["8-2-iliad.txt", "8-2-midsummer-night.txt", "8-2-paradise-lost.txt"];
+        let files = Files::new(&names);
+        let flags = Flags::new(&["-n", "-E"]);
+        let pattern = "^[A-Z][a-z]+ [A-Z]";
+        let expected = grep(pattern, &flags, files.as_ref()).unwrap();
+        assert!(!expected.is_empty());
+        assert_eq!(grep_parallel(pattern, &flags, files.as_ref(), 4).unwrap(), expected);
+        assert!(grep_parallel("x", &flags, &["8-2-missing.txt", files.as_ref()[0]], 2).is_err());
+    }
+
+    #[test]
+    fn search_counts_selected_lines() {
+        let files = Files::new(&["9-1-iliad.txt", "9-1-paradise-lost.txt"]);
+        let search = search("Of", &Flags::new(&["-c"]), files.as_ref()).unwrap();
+        assert_eq!(search.lines, ["9-1-iliad.txt:1", "9-1-paradise-lost.txt:3"]);
+        assert_eq!(search.selected, 4);
+
+        let search = search_reader("Hector", &Flags::new(&["-c"]), ILIAD_CONTENT.as_bytes());
+        let search = search.unwrap();
+        assert_eq!((search.lines, search.selected), (vec!["0".to_string()], 0));
+    }
+
+    #[test]
+    fn aho_corasick_finds_leftmost_longest_match() {
+        let set = AhoCorasick::new(&["he", "she", "his", "hers"], false);
+        assert!(set.is_match("ushers"));
+        assert!(!set.is_match("ushr"));
+        assert_eq!(set.find_at("ushers", 0), Some((1, 4)));
+        assert_eq!(set.find_at("ushers", 2), Some((2, 6)));
+        assert_eq!(set.find_at("ahishers", 0), Some((1, 4)));
+
+        let set = AhoCorasick::new(&["bcd", "abcdef", "c"], false);
+        assert_eq!(set.find_at("xabcxeg", 0), Some((3, 4)));
+        assert_eq!(set.find_at("xabcdeg", 0), Some((2, 5)));
+        assert_eq!(set.find_at("xabcdefg", 0), Some((1, 7)));
+
+        let set = AhoCorasick::new(&["МЕСЯЦ", "ночь"], true);
+        assert_eq!(set.find_at("Красный месяц", 0), Some((15, 25)));
+        assert!(!AhoCorasick::new(&[], false).is_match("anything"));
+        assert_eq!(AhoCorasick::new(&[""], false).find_at("ab", 1), Some((1, 1)));
+    }
+
+    #[test]
+    fn multiple_fixed_patterns() {
+        let files = Files::new(&["10-1-iliad.txt", "10-1-midsummer-night.txt"]);
+        let patterns = ["Achilles", "(so stood", "befall", "refuse"];
+        let flags = Flags::new(&["-n"]);
+        let actual = grep_patterns(&patterns, &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "10-1-iliad.txt:1:Achilles sing, O Goddess! Peleus' son;",
+            "10-1-iliad.txt:5:And Heroes gave (so stood the will of Jove)",
+            "10-1-iliad.txt:8:The noble Chief Achilles from the son",
+            "10-1-midsummer-night.txt:6:The worst that may befall me in this case,",
+            "10-1-midsummer-night.txt:7:If I refuse to wed Demetrius.",
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn multiple_patterns_with_flags() {
+        let files = Files::new(&["10-2-iliad.txt"]);
+        let patterns = ["OF ATREUS, AGAMEMNON, KING OF MEN.", "achilles", "His wrath"];
+
+        let flags = Flags::new(&["-x", "-i", "-n"]);
+        let actual = grep_patterns(&patterns, &flags, files.as_ref()).unwrap();
+        assert_eq!(actual, ["9:Of Atreus, Agamemnon, King of men."]);
+
+        let flags = Flags::new(&["-v", "-c", "-i"]);
+        let actual = grep_patterns(&patterns, &flags, files.as_ref()).unwrap();
+        assert_eq!(actual, ["5"]);
+
+        let flags = Flags::new(&["-o", "-i"]);
+        let actual = grep_patterns(&["so", "son", "o"], &flags, files.as_ref()).unwrap();
+        assert_eq!(actual.iter().filter(|part| *part == "son").count(), 2);
+        assert_eq!(actual.iter().filter(|part| *part == "so").count(), 2);
+
+        let flags = Flags::new(&["-E", "-n"]);
+        let actual = grep_patterns(&["^Of", "s$"], &flags, files.as_ref()).unwrap();
+        let expected: &[&str] = &[
+            "2:His wrath pernicious, who ten thousand woes",
+            "9:Of Atreus, Agamemnon, King of men.",
+        ];
+        assert_eq!(actual, expected);
+        assert!(grep_patterns(&["ok", "bad("], &flags, files.as_ref()).is_err());
+    }
+
+    #[test]
+    fn empty_pattern_set_selects_nothing() {
+        let files = Files::new(&["10-3-iliad.txt"]);
+        assert!(grep_patterns(&[], &Flags::new(&[]), files.as_ref()).unwrap().is_empty());
+        assert!(grep_patterns(&[], &Flags::new(&["-E"]), files.as_ref()).unwrap().is_empty());
+        assert_eq!(grep_patterns(&[], &Flags::new(&["-v", "-c"]), files.as_ref()).unwrap(), ["9"]);
+
+        let input = "\nfirst\n\nsecond".as_bytes();
+        let search = search_reader_patterns(&["", "zzz"], &Flags::new(&["-x", "-n"]), input);
+        assert_eq!(search.unwrap().lines, ["1:", "3:"]);
+    }
+
     static ILIAD_CONTENT: &str = "\
 Achilles sing, O Goddess! Peleus' son;
 His wrath pernicious, who ten thousand woes
@@ -496,16 +1995,7 @@ That Shepherd, who first taught the chosen Seed";
     impl<'a> Files<'a> {
         pub fn new(file_names: &'a [&'a str]) -> Self {
             for file_name in file_names {
-                let content = if file_name.ends_with("iliad.txt") {
-                    ILIAD_CONTENT
-                } else if file_name.ends_with("midsummer-night.txt") {
-                    MIDSUMMER_NIGHT_CONTENT
-                } else if file_name.ends_with("paradise-lost.txt") {
-                    PARADISE_LOST_CONTENT
-                } else {
-                    IN_THE_WHITE_NIGHT_CONTENT
-                };
-
+                let content = Self::content(file_name);
                 std::fs::write(file_name, content).unwrap_or_else(|_| {
                     panic!(
                         "Error setting up file '{file_name}' with the following content:\n{content}"
@@ -515,6 +2005,18 @@ That Shepherd, who first taught the chosen Seed";
 
             Self { file_names }
         }
+
+        fn content(file_name: &str) -> &'static str {
+            if file_name.contains("iliad.") {
+                ILIAD_CONTENT
+            } else if file_name.contains("midsummer-night.") {
+                MIDSUMMER_NIGHT_CONTENT
+            } else if file_name.contains("paradise-lost.") {
+                PARADISE_LOST_CONTENT
+            } else {
+                IN_THE_WHITE_NIGHT_CONTENT
+            }
+        }
     }
 
     impl Drop for Files<'_> {
@@ -526,6 +2028,29 @@ That Shepherd, who first taught the chosen Seed";
         }
     }
 
+    /// Directory tree of sample files, removed on drop
+    struct Dir {
+        path: &'static str,
+    }
+
+    impl Dir {
+        fn new(path: &'static str, file_names: &[&str]) -> Self {
+            for file_name in file_names {
+                let file_name = std::path::Path::new(path).join(file_name);
+                std::fs::create_dir_all(file_name.parent().unwrap()).unwrap();
+                std::fs::write(&file_name, Files::content(&file_name.to_string_lossy())).unwrap();
+            }
+            Self { path }
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(self.path)
+                .unwrap_or_else(|e| panic!("Could not delete directory '{}': {e}", self.path));
+        }
+    }
+
     impl<'a> AsRef<[&'a str]> for Files<'a> {
         fn as_ref(&self) -> &[&'a str] {
             self.file_names